@@ -0,0 +1,194 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{OnceLock, RwLock};
+use tauri::regex::Regex;
+
+use crate::http_client;
+
+const USER_AGENT_MOBILE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 16_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.6 Mobile/15E148 Safari/604.1";
+
+/// A source of video metadata. Every backend resolves an aweme id to the
+/// same item object that the v2 iteminfo API returns in `item_list[0]`.
+#[async_trait]
+pub trait ApiBackend: Send + Sync {
+    /// Stable name used in the configured order and reported to the UI
+    fn name(&self) -> &'static str;
+
+    /// Fetches the aweme item for `id`
+    async fn fetch_item(&self, id: &str) -> Result<serde_json::Value, String>;
+}
+
+/// The original `web/api/v2/aweme/iteminfo` JSON API
+pub struct ItemInfoBackend;
+
+#[async_trait]
+impl ApiBackend for ItemInfoBackend {
+    fn name(&self) -> &'static str {
+        "iteminfo"
+    }
+
+    async fn fetch_item(&self, id: &str) -> Result<serde_json::Value, String> {
+        let res_text = http_client::fetch_text(http_client::metadata_client().get(
+            "https://www.iesdouyin.com/web/api/v2/aweme/iteminfo/?item_ids=".to_string() + id,
+        ))
+        .await?;
+        let raw_info =
+            serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
+
+        first_item(&raw_info).ok_or_else(|| "接口未返回视频".into())
+    }
+}
+
+/// Parses the server-rendered share page (`iesdouyin.com/share/video/<id>`)
+pub struct SharePageBackend;
+
+#[async_trait]
+impl ApiBackend for SharePageBackend {
+    fn name(&self) -> &'static str {
+        "share_page"
+    }
+
+    async fn fetch_item(&self, id: &str) -> Result<serde_json::Value, String> {
+        let html = http_client::fetch_text(
+            http_client::metadata_client()
+                .get(format!("https://www.iesdouyin.com/share/video/{id}/"))
+                .header("user-agent", USER_AGENT_MOBILE),
+        )
+        .await?;
+
+        parse_share_page(&html).ok_or_else(|| "分享页解析失败".into())
+    }
+}
+
+/// A user supplied endpoint. `{id}` in the template is replaced by the aweme
+/// id; the response may be iteminfo-shaped, `aweme_detail`-shaped or the bare item.
+pub struct CustomEndpointBackend {
+    pub url_template: String,
+}
+
+#[async_trait]
+impl ApiBackend for CustomEndpointBackend {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    async fn fetch_item(&self, id: &str) -> Result<serde_json::Value, String> {
+        if self.url_template.is_empty() {
+            return Err("未配置自定义接口".into());
+        }
+
+        let res_text = http_client::fetch_text(
+            http_client::metadata_client().get(self.url_template.replace("{id}", id)),
+        )
+        .await?;
+        let raw_info =
+            serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
+
+        first_item(&raw_info).ok_or_else(|| "接口未返回视频".into())
+    }
+}
+
+/// Which backends to try, and in which order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendConfig {
+    pub order: Vec<String>,
+    pub custom_endpoint: Option<String>,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig {
+            order: vec!["iteminfo".into(), "share_page".into(), "custom".into()],
+            custom_endpoint: None,
+        }
+    }
+}
+
+fn backend_config() -> &'static RwLock<BackendConfig> {
+    static CONFIG: OnceLock<RwLock<BackendConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| RwLock::new(BackendConfig::default()))
+}
+
+/// Returns a snapshot of the current backend configuration
+pub fn current_config() -> BackendConfig {
+    backend_config().read().unwrap().clone()
+}
+
+/// Replaces the backend configuration, rejecting unknown backend names
+pub fn update_config(config: BackendConfig) -> Result<(), String> {
+    if let Some(name) = config
+        .order
+        .iter()
+        .find(|name| build_backend(name, &config).is_none())
+    {
+        return Err(format!("未知接口: {}", name));
+    }
+
+    *backend_config().write().unwrap() = config;
+
+    Ok(())
+}
+
+fn build_backend(name: &str, config: &BackendConfig) -> Option<Box<dyn ApiBackend>> {
+    match name {
+        "iteminfo" => Some(Box::new(ItemInfoBackend)),
+        "share_page" => Some(Box::new(SharePageBackend)),
+        "custom" => Some(Box::new(CustomEndpointBackend {
+            url_template: config.custom_endpoint.clone().unwrap_or_default(),
+        })),
+        _ => None,
+    }
+}
+
+/// Tries every configured backend in order and returns the first item found
+/// together with the name of the backend that produced it
+pub async fn fetch_item_with_fallback(id: &str) -> Result<(serde_json::Value, String), String> {
+    let config = current_config();
+    let mut errors = vec![];
+
+    for backend in config
+        .order
+        .iter()
+        .filter_map(|name| build_backend(name, &config))
+    {
+        match backend.fetch_item(id).await {
+            Ok(item) => return Ok((item, backend.name().to_string())),
+            Err(e) => errors.push(format!("{}: {}", backend.name(), e)),
+        }
+    }
+
+    if errors.is_empty() {
+        return Err("未配置可用接口".into());
+    }
+
+    Err(errors.join("; "))
+}
+
+/// Picks the aweme item out of the response shapes the various endpoints use
+fn first_item(raw_info: &serde_json::Value) -> Option<serde_json::Value> {
+    let item = if raw_info["item_list"].is_array() {
+        &raw_info["item_list"][0]
+    } else if raw_info["aweme_detail"].is_object() {
+        &raw_info["aweme_detail"]
+    } else {
+        raw_info
+    };
+
+    match item["video"].is_object() {
+        true => Some(item.clone()),
+        _ => None,
+    }
+}
+
+/// Extracts the item embedded as `window._ROUTER_DATA` in the share page
+fn parse_share_page(html: &str) -> Option<serde_json::Value> {
+    let reg_router_data =
+        Regex::new(r#"(?s)window\._ROUTER_DATA\s*=\s*(\{.*?\})\s*</script>"#).unwrap();
+    let router_data = reg_router_data.captures(html)?.get(1)?.as_str();
+    let raw_info = serde_json::from_str::<serde_json::Value>(router_data).ok()?;
+
+    raw_info["loaderData"]
+        .as_object()?
+        .values()
+        .find_map(|page| first_item(&page["videoInfoRes"]))
+}
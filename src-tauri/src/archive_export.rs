@@ -0,0 +1,561 @@
+use chrono::{Datelike, Timelike};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::file_finalizer::{self, ConflictPolicy};
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Volumes smaller than this would produce thousands of files for one creator
+const MIN_SPLIT_SIZE: u64 = 1024 * 1024;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Already compressed media is stored as is; deflating it only costs time
+const STORED_EXTENSIONS: [&str; 8] = ["mp4", "m4a", "mp3", "jpg", "jpeg", "png", "webp", "gif"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Payload of `e_archive_progress`
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveProgress {
+    pub output: String,
+    pub current_file: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveResult {
+    /// The archive itself, or its `.001`, `.002`, ... parts when split
+    pub volumes: Vec<String>,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// One entry of `manifest.json`
+#[derive(Debug, Clone, Serialize)]
+struct ManifestEntry {
+    path: String,
+    size: u64,
+    sha256: String,
+    modified: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Manifest {
+    source: String,
+    created_at: String,
+    files: Vec<ManifestEntry>,
+}
+
+struct SourceFile {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Packs every file below `source_dir` into `output` under a folder named
+/// after the source, followed by a `manifest.json` with sizes and SHA-256
+/// sums. Files are streamed, so memory use does not grow with their size.
+/// With `split_size` the archive is cut into volumes of that many bytes that
+/// can be joined again with `cat` or opened directly by 7-Zip.
+pub fn create(
+    source_dir: &Path,
+    output: &Path,
+    format: ArchiveFormat,
+    split_size: Option<u64>,
+    on_progress: &dyn Fn(&ArchiveProgress),
+) -> Result<ArchiveResult, String> {
+    if split_size.is_some_and(|size| size < MIN_SPLIT_SIZE) {
+        return Err("分卷大小不能小于 1 MB".into());
+    }
+
+    let root = source_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or("目录无效")?;
+    let output_dir = output
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .canonicalize()
+        .ok();
+    let output_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or("输出路径无效")?;
+    let mut files = Vec::new();
+
+    collect_files(
+        source_dir,
+        &root,
+        (output_dir.as_deref(), &output_name),
+        &mut files,
+    )?;
+
+    let bytes_total = files.iter().map(|file| file.size).sum::<u64>();
+
+    file_finalizer::check_free_space(output.parent().unwrap_or(Path::new(".")), bytes_total)?;
+
+    let mut progress = Progress {
+        state: ArchiveProgress {
+            output: output.to_string_lossy().into(),
+            current_file: String::new(),
+            files_done: 0,
+            files_total: files.len(),
+            bytes_done: 0,
+            bytes_total,
+        },
+        last_emit: None,
+        on_progress,
+    };
+    let mut volumes = VolumeWriter::new(output, split_size);
+    let written = match format {
+        ArchiveFormat::Zip => write_zip(&mut volumes, &root, &files, &mut progress),
+        ArchiveFormat::TarGz => write_tar_gz(&mut volumes, &root, &files, &mut progress),
+    };
+
+    if let Err(e) = written {
+        volumes.discard();
+        return Err(e);
+    }
+
+    let volumes = volumes.finish()?;
+
+    progress.emit(true);
+
+    Ok(ArchiveResult {
+        volumes: volumes
+            .iter()
+            .map(|volume| volume.to_string_lossy().into())
+            .collect(),
+        files: files.len(),
+        bytes: bytes_total,
+    })
+}
+
+/// Regular files below `dir`, sorted. Hidden files (including unfinished
+/// `.part` downloads), symlinks, a previous manifest and the archive being
+/// written (with its volumes, when `output` lies inside the source) are left out.
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    output: (Option<&Path>, &str),
+    files: &mut Vec<SourceFile>,
+) -> Result<(), String> {
+    let (output_dir, output_name) = output;
+    let holds_output =
+        output_dir.is_some_and(|output_dir| dir.canonicalize().is_ok_and(|dir| dir == output_dir));
+    let mut entries = fs::read_dir(dir)
+        .map_err(|_| format!("目录读取失败: {}", dir.to_string_lossy()))?
+        .flatten()
+        .collect::<Vec<fs::DirEntry>>();
+
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Ok(metadata) = fs::symlink_metadata(entry.path()) else {
+            continue;
+        };
+
+        if name.starts_with('.')
+            || (!prefix.contains('/') && name == MANIFEST_NAME)
+            || (holds_output && is_output_volume(&name, output_name))
+        {
+            continue;
+        }
+
+        let archive_name = format!("{}/{}", prefix, name);
+
+        if metadata.is_dir() {
+            collect_files(&entry.path(), &archive_name, output, files)?;
+        } else if metadata.is_file() {
+            files.push(SourceFile {
+                path: entry.path(),
+                name: archive_name,
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// `name` is `output_name` itself or one of its `.001`, `.002`, ... volumes
+fn is_output_volume(name: &str, output_name: &str) -> bool {
+    match name.strip_prefix(output_name) {
+        Some("") => true,
+        Some(suffix) => suffix.strip_prefix('.').is_some_and(|number| {
+            number.len() >= 3 && number.bytes().all(|byte| byte.is_ascii_digit())
+        }),
+        None => false,
+    }
+}
+
+fn write_zip(
+    volumes: &mut VolumeWriter,
+    root: &str,
+    files: &[SourceFile],
+    progress: &mut Progress,
+) -> Result<(), String> {
+    let mut zip = ZipWriter::new(BufWriter::new(volumes));
+    let mut manifest = Vec::with_capacity(files.len());
+
+    for file in files {
+        let extension = Path::new(&file.name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let method = match STORED_EXTENSIONS.contains(&extension.as_str()) {
+            true => CompressionMethod::Stored,
+            _ => CompressionMethod::Deflated,
+        };
+        let options = FileOptions::default()
+            .compression_method(method)
+            .last_modified_time(zip_time(file.modified))
+            .unix_permissions(0o644)
+            .large_file(file.size >= u32::MAX as u64);
+
+        zip.start_file(file.name.as_str(), options)
+            .map_err(|_| "压缩包写入失败")?;
+        manifest.push(copy_entry(file, &mut zip, progress)?);
+    }
+
+    zip.start_file(
+        format!("{}/{}", root, MANIFEST_NAME),
+        FileOptions::default().unix_permissions(0o644),
+    )
+    .map_err(|_| "压缩包写入失败")?;
+    zip.write_all(&manifest_json(root, manifest)?)
+        .map_err(|_| "压缩包写入失败")?;
+    zip.finish()
+        .map_err(|_| "压缩包写入失败")?
+        .flush()
+        .map_err(|_| "压缩包写入失败")?;
+
+    Ok(())
+}
+
+fn write_tar_gz(
+    volumes: &mut VolumeWriter,
+    root: &str,
+    files: &[SourceFile],
+    progress: &mut Progress,
+) -> Result<(), String> {
+    let encoder = GzEncoder::new(BufWriter::new(volumes), Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    let mut manifest = Vec::with_capacity(files.len());
+
+    for file in files {
+        let mut header = tar::Header::new_gnu();
+        let mut source = HashingReader::open(file, progress)?;
+
+        header.set_size(file.size);
+        header.set_mode(0o644);
+        header.set_mtime(
+            file.modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        tar.append_data(&mut header, &file.name, &mut source)
+            .map_err(|_| "压缩包写入失败")?;
+        manifest.push(source.finish(file)?);
+    }
+
+    let manifest = manifest_json(root, manifest)?;
+    let mut header = tar::Header::new_gnu();
+
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    tar.append_data(
+        &mut header,
+        format!("{}/{}", root, MANIFEST_NAME),
+        manifest.as_slice(),
+    )
+    .map_err(|_| "压缩包写入失败")?;
+    tar.into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| writer.flush())
+        .map_err(|_| "压缩包写入失败")?;
+
+    Ok(())
+}
+
+fn copy_entry(
+    file: &SourceFile,
+    target: &mut impl Write,
+    progress: &mut Progress,
+) -> Result<ManifestEntry, String> {
+    let mut source = HashingReader::open(file, progress)?;
+
+    io::copy(&mut source, target).map_err(|_| "压缩包写入失败")?;
+    source.finish(file)
+}
+
+fn manifest_json(root: &str, files: Vec<ManifestEntry>) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(&Manifest {
+        source: root.to_string(),
+        created_at: chrono::Local::now().to_rfc3339(),
+        files,
+    })
+    .map_err(|_| "清单生成失败".into())
+}
+
+/// Zip stores local time with two second precision and nothing before 1980
+fn zip_time(time: SystemTime) -> zip::DateTime {
+    let time = chrono::DateTime::<chrono::Local>::from(time);
+
+    zip::DateTime::from_date_and_time(
+        time.year().clamp(1980, 2107) as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Reads a source file while hashing it and reporting progress
+struct HashingReader<'a, 'b> {
+    file: File,
+    hasher: Sha256,
+    read: u64,
+    progress: &'a mut Progress<'b>,
+}
+
+impl<'a, 'b> HashingReader<'a, 'b> {
+    fn open(file: &SourceFile, progress: &'a mut Progress<'b>) -> Result<Self, String> {
+        progress.state.current_file = file.name.clone();
+        progress.emit(true);
+
+        Ok(HashingReader {
+            file: File::open(&file.path)
+                .map_err(|_| format!("文件打开失败: {}", file.path.to_string_lossy()))?,
+            hasher: Sha256::new(),
+            read: 0,
+            progress,
+        })
+    }
+
+    fn finish(self, file: &SourceFile) -> Result<ManifestEntry, String> {
+        // The file changed while it was being packed
+        if self.read != file.size {
+            return Err(format!("文件大小已变化: {}", file.name));
+        }
+
+        self.progress.state.files_done += 1;
+
+        Ok(ManifestEntry {
+            path: file.name.clone(),
+            size: file.size,
+            sha256: hex::encode(self.hasher.finalize()),
+            modified: chrono::DateTime::<chrono::Local>::from(file.modified).to_rfc3339(),
+        })
+    }
+}
+
+impl Read for HashingReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.file.read(buf)?;
+
+        self.hasher.update(&buf[..len]);
+        self.read += len as u64;
+        self.progress.state.bytes_done += len as u64;
+        self.progress.emit(false);
+
+        Ok(len)
+    }
+}
+
+struct Progress<'a> {
+    state: ArchiveProgress,
+    last_emit: Option<Instant>,
+    on_progress: &'a dyn Fn(&ArchiveProgress),
+}
+
+impl Progress<'_> {
+    fn emit(&mut self, force: bool) {
+        let now = Instant::now();
+
+        if !force
+            && self
+                .last_emit
+                .is_some_and(|last| now - last < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_emit = Some(now);
+        (self.on_progress)(&self.state);
+    }
+}
+
+/// A seekable output that is cut into numbered volumes of `split_size`
+/// bytes. Volumes are written as hidden temp files and only renamed to their
+/// final names once the whole archive is complete.
+struct VolumeWriter {
+    output: PathBuf,
+    split_size: Option<u64>,
+    volumes: Vec<(File, PathBuf)>,
+    position: u64,
+    len: u64,
+}
+
+impl VolumeWriter {
+    fn new(output: &Path, split_size: Option<u64>) -> Self {
+        VolumeWriter {
+            output: output.to_path_buf(),
+            split_size,
+            volumes: Vec::new(),
+            position: 0,
+            len: 0,
+        }
+    }
+
+    fn final_path(&self, index: usize) -> PathBuf {
+        match self.split_size {
+            Some(_) => self.numbered_path(index),
+            None => self.output.clone(),
+        }
+    }
+
+    fn numbered_path(&self, index: usize) -> PathBuf {
+        let mut name = self.output.as_os_str().to_owned();
+
+        name.push(format!(".{:03}", index + 1));
+        PathBuf::from(name)
+    }
+
+    /// The volume holding `position` and the offset inside it
+    fn locate(&self, position: u64) -> (usize, u64) {
+        match self.split_size {
+            Some(size) => ((position / size) as usize, position % size),
+            None => (0, position),
+        }
+    }
+
+    fn volume(&mut self, index: usize) -> io::Result<&mut File> {
+        while self.volumes.len() <= index {
+            let temp = file_finalizer::temp_path_for(&self.final_path(self.volumes.len()));
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+
+            self.volumes.push((file, temp));
+        }
+
+        Ok(&mut self.volumes[index].0)
+    }
+
+    fn finish(mut self) -> Result<Vec<PathBuf>, String> {
+        let mut finished = Vec::with_capacity(self.volumes.len());
+
+        for (index, (file, temp)) in std::mem::take(&mut self.volumes).into_iter().enumerate() {
+            drop(file);
+
+            let target = self.final_path(index);
+
+            file_finalizer::finalize(&temp, &target, ConflictPolicy::Overwrite)
+                .inspect_err(|_| file_finalizer::discard(&temp))?;
+            finished.push(target);
+        }
+
+        // A previous, longer export to the same path would otherwise leave
+        // trailing volumes that no longer belong to this archive
+        let first_stale = match self.split_size {
+            Some(_) => finished.len(),
+            None => 0,
+        };
+
+        for index in first_stale.. {
+            if fs::remove_file(self.numbered_path(index)).is_err() {
+                break;
+            }
+        }
+
+        Ok(finished)
+    }
+
+    fn discard(&mut self) {
+        for (_, temp) in self.volumes.drain(..) {
+            file_finalizer::discard(&temp);
+        }
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (index, offset) = self.locate(self.position);
+        let room = match self.split_size {
+            Some(size) => (size - offset) as usize,
+            None => buf.len(),
+        };
+        let len = buf.len().min(room);
+        let volume = self.volume(index)?;
+
+        volume.seek(SeekFrom::Start(offset))?;
+        volume.write_all(&buf[..len])?;
+        self.position += len as u64;
+        self.len = self.len.max(self.position);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.volumes
+            .iter_mut()
+            .try_for_each(|(file, _)| file.flush())
+    }
+}
+
+impl Seek for VolumeWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        Ok(self.position)
+    }
+}
@@ -1,98 +1,410 @@
-use std::fs::File;
-use std::io::BufReader;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use tar::{Archive, EntryType};
+use zip::result::ZipError;
 use zip::ZipArchive;
-use tar::Archive;
-
-/// Handles media archive processing
-pub fn handle_media_archive(raw_path: String) {
-    let processed_path = normalize_media_path(raw_path);
-    let validated_path = validate_archive_format(processed_path);
-    let final_path = prepare_extraction_target(validated_path);
-    
-    // Process both zip and tar archives
-    extract_media_archives(final_path);
+
+/// Archives below this size may expand past the ratio limit without being bombs
+const RATIO_GRACE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Unix file type bits as stored in zip external attributes
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// Where archives may be extracted to and how much they may expand
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportConfig {
+    pub library_root: Option<String>,
+    pub max_entries: u64,
+    pub max_entry_bytes: u64,
+    pub max_total_bytes: u64,
+    /// Extracted bytes per archive byte
+    pub max_ratio: u64,
 }
 
-/// Normalizes the media file path for processing
-fn normalize_media_path(path: String) -> String {
-    // Remove any URL encoding and normalize separators
-    let decoded = path.replace("%2F", "/").replace("%5C", "\\");
-    let normalized = decoded.replace("\\", "/");
-    
-    // Add media directory prefix if not present
-    if !normalized.starts_with("/media/") && !normalized.starts_with("media/") {
-        format!("media/{}", normalized)
-    } else {
-        normalized
+impl Default for ImportConfig {
+    fn default() -> Self {
+        ImportConfig {
+            library_root: None,
+            max_entries: 10_000,
+            max_entry_bytes: 8 * 1024 * 1024 * 1024,
+            max_total_bytes: 32 * 1024 * 1024 * 1024,
+            max_ratio: 100,
+        }
     }
 }
 
-/// Validates and enriches archive format information
-fn validate_archive_format(path: String) -> String {
-    // Check for supported archive extensions
-    let supported_formats = vec![".zip", ".tar", ".tar.gz", ".tgz"];
-    let mut enhanced_path = path.clone();
-    
-    // Add format-specific processing metadata
-    for format in supported_formats {
-        if path.ends_with(format) {
-            enhanced_path = format!("{}?format={}&priority=high", path, format.trim_start_matches('.'));
-            break;
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportResult {
+    /// Top-level files and folders added to the library root
+    pub imported: Vec<String>,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+fn import_config() -> &'static RwLock<ImportConfig> {
+    static CONFIG: OnceLock<RwLock<ImportConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| RwLock::new(ImportConfig::default()))
+}
+
+pub fn current_config() -> ImportConfig {
+    import_config().read().unwrap().clone()
+}
+
+pub fn update_config(config: ImportConfig) -> Result<(), String> {
+    if config.max_entries == 0
+        || config.max_entry_bytes == 0
+        || config.max_total_bytes == 0
+        || config.max_ratio == 0
+    {
+        return Err("导入限制必须大于 0".into());
+    }
+
+    if let Some(root) = &config.library_root {
+        if !Path::new(root).is_dir() {
+            return Err("媒体库目录不存在".into());
         }
     }
-    
-    // Add timestamp for processing tracking
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    
-    format!("{}&timestamp={}", enhanced_path, timestamp)
+
+    *import_config().write().unwrap() = config;
+
+    Ok(())
 }
 
-/// Prepares the final extraction target path
-fn prepare_extraction_target(enriched_path: String) -> String {
-    // Extract the base path from the enriched format
-    let base_path = enriched_path.split('?').next().unwrap_or(&enriched_path).to_string();
-    
-    // Apply business logic for extraction location
-    let extraction_base = if base_path.contains("premium") {
-        format!("/premium_content/{}", base_path)
-    } else if base_path.contains("user_") {
-        format!("/user_uploads/{}", base_path)
-    } else {
-        format!("/downloads/{}", base_path)
+/// Imports into the configured library root
+pub fn import_into_library(archive: &Path, password: Option<&str>) -> Result<ImportResult, String> {
+    let root = current_config().library_root.ok_or("未设置媒体库目录")?;
+
+    import_archive(archive, Path::new(&root), password)
+}
+
+/// Extracts a zip, tar or tar.gz archive below `library_root`.
+///
+/// Entries are written to a hidden staging folder inside the root first and
+/// only moved into place once the whole archive has been extracted, so a
+/// rejected archive leaves nothing behind. The archive is rejected when any
+/// entry is absolute, climbs out with `..`, is a link or a device file, or
+/// when it expands beyond the configured limits. Existing top-level names are
+/// never overwritten; the imported copy gets a ` (1)` suffix instead.
+pub fn import_archive(
+    archive: &Path,
+    library_root: &Path,
+    password: Option<&str>,
+) -> Result<ImportResult, String> {
+    let root = library_root
+        .canonicalize()
+        .map_err(|_| "媒体库目录不存在")?;
+    let archive_len = fs::metadata(archive).map_err(|_| "压缩包不存在")?.len();
+    let staging = root.join(format!(
+        ".import-{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+
+    fs::create_dir(&staging).map_err(|_| "无法在媒体库目录中创建临时目录")?;
+
+    let mut extractor = Extractor {
+        staging: staging.clone(),
+        limits: current_config(),
+        archive_len,
+        entries: 0,
+        files: 0,
+        bytes: 0,
     };
-    
-    // Add processing metadata for tracking
-    format!("{}.processing", extraction_base)
+    let result = extractor
+        .extract(archive, password)
+        .and_then(|_| move_into_place(&staging, &root));
+
+    let _ = fs::remove_dir_all(&staging);
+
+    Ok(ImportResult {
+        imported: result?,
+        files: extractor.files,
+        bytes: extractor.bytes,
+    })
 }
 
-/// Extracts media archives using both zip and tar methods
-fn extract_media_archives(tainted_path: String) {
-    // Remove processing suffix for actual file operations
-    let clean_path = tainted_path.replace(".processing", "");
-    
-    // Try ZIP extraction first
-    if let Ok(file) = File::open(&clean_path) {
-        let reader = BufReader::new(file);
-        if let Ok(mut archive) = ZipArchive::new(reader) {
-            let password = b"default_password";
-            //SINK
-            let _ = archive.by_name_decrypt(&clean_path, password);
+struct Extractor {
+    staging: PathBuf,
+    limits: ImportConfig,
+    archive_len: u64,
+    entries: u64,
+    files: u64,
+    bytes: u64,
+}
+
+impl Extractor {
+    fn extract(&mut self, archive: &Path, password: Option<&str>) -> Result<(), String> {
+        let mut file = File::open(archive).map_err(|_| "压缩包打开失败")?;
+        let mut magic = [0; 4];
+        let magic_len = file.read(&mut magic).map_err(|_| "压缩包读取失败")?;
+
+        file.seek(SeekFrom::Start(0))
+            .map_err(|_| "压缩包读取失败")?;
+
+        match &magic[..magic_len] {
+            [b'P', b'K', 3, 4] | [b'P', b'K', 5, 6] => self.extract_zip(file, password),
+            [0x1f, 0x8b, ..] => self.extract_tar(GzDecoder::new(BufReader::new(file))),
+            _ => self.extract_tar(BufReader::new(file)),
+        }
+    }
+
+    fn extract_zip(&mut self, file: File, password: Option<&str>) -> Result<(), String> {
+        let mut zip = ZipArchive::new(BufReader::new(file)).map_err(|_| "压缩包格式错误")?;
+
+        for index in 0..zip.len() {
+            let mut entry = match password {
+                Some(password) => zip
+                    .by_index_decrypt(index, password.as_bytes())
+                    .map_err(zip_error)?
+                    .map_err(|_| "压缩包密码错误")?,
+                None => zip.by_index(index).map_err(zip_error)?,
+            };
+            let name = entry.name().to_string();
+            let path = sanitize_entry_path(&name)?;
+            let file_type = entry.unix_mode().map(|mode| mode & S_IFMT);
+
+            self.count_entry()?;
+
+            if entry.is_dir() || file_type == Some(S_IFDIR) {
+                self.create_dir(&path)?;
+                continue;
+            }
+
+            if file_type.is_some_and(|file_type| file_type != S_IFREG && file_type != 0) {
+                return Err(format!("压缩包包含链接或设备文件: {}", name));
+            }
+
+            // Declared sizes can lie, so the copy enforces the limits again
+            if entry.size() > self.limits.max_entry_bytes {
+                return Err(format!("解压后文件过大: {}", name));
+            }
+
+            self.write_file(&path, &name, &mut entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn extract_tar(&mut self, reader: impl Read) -> Result<(), String> {
+        let mut tar = Archive::new(reader);
+
+        for entry in tar.entries().map_err(|_| "压缩包格式错误")? {
+            let mut entry = entry.map_err(|_| "压缩包格式错误")?;
+            let name = entry
+                .path()
+                .map_err(|_| "压缩包条目路径非法")?
+                .to_string_lossy()
+                .to_string();
+
+            match entry.header().entry_type() {
+                EntryType::XGlobalHeader => continue,
+                EntryType::Regular | EntryType::Continuous => {
+                    let path = sanitize_entry_path(&name)?;
+
+                    self.count_entry()?;
+                    self.write_file(&path, &name, &mut entry)?;
+                }
+                EntryType::Directory => {
+                    let path = sanitize_entry_path(&name)?;
+
+                    self.count_entry()?;
+                    self.create_dir(&path)?;
+                }
+                _ => return Err(format!("压缩包包含链接或设备文件: {}", name)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count_entry(&mut self) -> Result<(), String> {
+        self.entries += 1;
+
+        if self.entries > self.limits.max_entries {
+            return Err("压缩包条目过多".into());
+        }
+
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), String> {
+        fs::create_dir_all(self.staging.join(path)).map_err(|_| "目录创建失败".into())
+    }
+
+    /// Copies one entry, stopping as soon as it or the whole import grows
+    /// past a limit
+    fn write_file(
+        &mut self,
+        path: &Path,
+        name: &str,
+        reader: &mut impl Read,
+    ) -> Result<(), String> {
+        let target = self.staging.join(path);
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|_| "目录创建失败")?;
         }
+
+        // create_new also refuses duplicate entries that would overwrite each other
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&target)
+            .map_err(|_| format!("文件创建失败: {}", name))?;
+        let mut buffer = vec![0; 64 * 1024];
+        let mut written = 0u64;
+
+        loop {
+            let len = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return Err(format!("解压失败: {}", name)),
+            };
+
+            written += len as u64;
+            self.bytes += len as u64;
+
+            if written > self.limits.max_entry_bytes {
+                return Err(format!("解压后文件过大: {}", name));
+            }
+
+            if self.bytes > self.limits.max_total_bytes {
+                return Err("解压后总大小超出限制".into());
+            }
+
+            if self.bytes > RATIO_GRACE_BYTES
+                && self.bytes / self.archive_len.max(1) > self.limits.max_ratio
+            {
+                return Err("压缩比异常，疑似压缩炸弹".into());
+            }
+
+            file.write_all(&buffer[..len]).map_err(|_| "文件写入失败")?;
+        }
+
+        self.files += 1;
+
+        Ok(())
     }
-    
-    // Try TAR extraction as fallback
-    if let Ok(file) = File::open(&clean_path) {
-        let reader = BufReader::new(file);
-        let mut archive = Archive::new(reader);
-        
-        if let Ok(entries) = archive.entries() {
-            for mut entry in entries.flatten() {
-                //SINK
-                let _ = entry.unpack(&clean_path);
+}
+
+/// Turns an archive entry name into a relative path, rejecting anything that
+/// could resolve outside the extraction folder
+fn sanitize_entry_path(name: &str) -> Result<PathBuf, String> {
+    let normalized = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+
+    if normalized.starts_with('/') {
+        return Err(format!("压缩包包含绝对路径: {}", name));
+    }
+
+    for part in normalized.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(format!("压缩包条目越界: {}", name)),
+            // Drive letters and alternate data streams on Windows
+            _ if part.contains(':') || part.contains('\0') => {
+                return Err(format!("压缩包条目路径非法: {}", name))
             }
+            _ => path.push(part),
         }
     }
-} 
\ No newline at end of file
+
+    if path.as_os_str().is_empty() {
+        return Err(format!("压缩包条目路径非法: {}", name));
+    }
+
+    Ok(path)
+}
+
+fn zip_error(e: ZipError) -> String {
+    match e {
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+            "压缩包已加密，需要密码".into()
+        }
+        ZipError::UnsupportedArchive(detail) => format!("不支持的压缩包: {}", detail),
+        _ => "压缩包格式错误".into(),
+    }
+}
+
+/// Moves the staged top-level entries into `root` without replacing anything
+fn move_into_place(staging: &Path, root: &Path) -> Result<Vec<String>, String> {
+    let mut names = fs::read_dir(staging)
+        .map_err(|_| "目录读取失败")?
+        .flatten()
+        .map(|entry| entry.file_name())
+        .collect::<Vec<_>>();
+    let mut imported = Vec::with_capacity(names.len());
+
+    names.sort();
+
+    for name in names {
+        let target = free_name(&root.join(&name));
+
+        fs::rename(staging.join(&name), &target).map_err(|_| "文件移动失败")?;
+        imported.push(target.to_string_lossy().to_string());
+    }
+
+    Ok(imported)
+}
+
+fn free_name(target: &Path) -> PathBuf {
+    if !target.exists() {
+        return target.to_path_buf();
+    }
+
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    (1..)
+        .map(|index| target.with_file_name(format!("{} ({})", name, index)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_keeps_plain_relative_paths() {
+        assert_eq!(sanitize_entry_path("a/b.mp4").unwrap(), Path::new("a").join("b.mp4"));
+        assert_eq!(sanitize_entry_path("./a//b.mp4").unwrap(), Path::new("a").join("b.mp4"));
+        assert_eq!(sanitize_entry_path("a\\b.mp4").unwrap(), Path::new("a").join("b.mp4"));
+    }
+
+    #[test]
+    fn sanitize_rejects_escaping_paths() {
+        for name in [
+            "../a.mp4",
+            "a/../../b.mp4",
+            "a\\..\\..\\b.mp4",
+            "/etc/passwd",
+            "\\windows\\system32",
+            "C:/a.mp4",
+            "C:a.mp4",
+            "a.mp4:stream",
+            "a\0.mp4",
+        ] {
+            assert!(sanitize_entry_path(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn sanitize_rejects_empty_paths() {
+        for name in ["", ".", "./", "//"] {
+            assert!(sanitize_entry_path(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::{ColorType, DynamicImage, ImageFormat, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+use crate::file_finalizer::{self, ConflictPolicy};
+use crate::http_client;
+use crate::rate_limiter;
+
+const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36";
+
+/// Covers and avatars are small; anything larger is not an image we want
+const MAX_IMAGE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Extensions originals are saved with, one per format the server may send
+const EXTENSIONS: [&str; 4] = ["jpg", "png", "webp", "gif"];
+
+/// Thumbnail settings. `thumbnail_size` bounds the longer side in pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtworkConfig {
+    pub thumbnail_size: u32,
+    pub jpeg_quality: u8,
+}
+
+impl Default for ArtworkConfig {
+    fn default() -> Self {
+        ArtworkConfig {
+            thumbnail_size: 320,
+            jpeg_quality: 85,
+        }
+    }
+}
+
+/// Files written for one image. `path` is the original as the server sent it,
+/// the thumbnail is always a JPEG.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedArtwork {
+    pub path: String,
+    pub thumbnail_path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn artwork_config() -> &'static RwLock<ArtworkConfig> {
+    static CONFIG: OnceLock<RwLock<ArtworkConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| RwLock::new(ArtworkConfig::default()))
+}
+
+pub fn current_config() -> ArtworkConfig {
+    artwork_config().read().unwrap().clone()
+}
+
+pub fn update_config(config: ArtworkConfig) -> Result<(), String> {
+    if config.thumbnail_size == 0 {
+        return Err("缩略图尺寸无效".into());
+    }
+
+    if !(1..=100).contains(&config.jpeg_quality) {
+        return Err("JPEG 质量需在 1 到 100 之间".into());
+    }
+
+    *artwork_config().write().unwrap() = config;
+
+    Ok(())
+}
+
+/// Downloads the image at `url` to `<stem>.<ext>` in `dir`, keeping the
+/// original bytes and format, and writes a thumbnail to `<stem>.thumb.jpg`
+pub async fn save(url: &str, dir: &Path, stem: &str) -> Result<SavedArtwork, String> {
+    let bytes = fetch(url).await?;
+    let dir = dir.to_path_buf();
+    let stem = stem.to_string();
+    let config = current_config();
+
+    // Decoding and encoding are CPU bound, keep them off the async workers
+    tokio::task::spawn_blocking(move || write_artwork(&bytes, &dir, &stem, &config))
+        .await
+        .map_err(|_| "图片处理失败")?
+}
+
+/// The original saved by `save` for `stem` in `dir`, whatever its format
+pub fn find(dir: &Path, stem: &str) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", stem, extension)))
+        .find(|path| path.is_file())
+}
+
+/// Fetches the image at `url` as JPEG bytes, converting other formats
+pub async fn fetch_jpeg(url: &str) -> Result<Vec<u8>, String> {
+    into_jpeg(fetch(url).await?).await
+}
+
+/// Reads the image at `path` as JPEG bytes, converting other formats
+pub async fn read_jpeg(path: &Path) -> Result<Vec<u8>, String> {
+    into_jpeg(std::fs::read(path).map_err(|_| "文件读取失败")?).await
+}
+
+async fn into_jpeg(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    let quality = current_config().jpeg_quality;
+
+    tokio::task::spawn_blocking(move || match image::guess_format(&bytes) {
+        Ok(ImageFormat::Jpeg) => Ok(bytes),
+        _ => encode_jpeg(
+            &image::load_from_memory(&bytes).map_err(|_| "图片解码失败")?,
+            quality,
+        ),
+    })
+    .await
+    .map_err(|_| "图片处理失败")?
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let permit = rate_limiter::acquire(&http_client::host_of(
+        &reqwest::Url::parse(url).map_err(|_| "图片地址无效")?,
+    ))
+    .await;
+    let mut res = http_client::media_client()
+        .get(url)
+        .header("user-agent", USER_AGENT)
+        .send()
+        .await
+        .map_err(|_| "网络错误")?;
+
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        permit.throttled();
+        return Err("请求过于频繁，已自动降速".into());
+    }
+
+    permit.succeeded();
+
+    if !res.status().is_success() {
+        return Err(format!("图片下载失败: {}", res.status()));
+    }
+
+    if res
+        .content_length()
+        .is_some_and(|len| len as usize > MAX_IMAGE_BYTES)
+    {
+        return Err("图片过大".into());
+    }
+
+    // Without a Content-Length the size is only known while reading
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = res.chunk().await.map_err(|_| "网络错误")? {
+        if bytes.len() + chunk.len() > MAX_IMAGE_BYTES {
+            return Err("图片过大".into());
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+fn write_artwork(
+    bytes: &[u8],
+    dir: &Path,
+    stem: &str,
+    config: &ArtworkConfig,
+) -> Result<SavedArtwork, String> {
+    let format = image::guess_format(bytes).map_err(|_| "不支持的图片格式")?;
+    let extension = match format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Png => "png",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Gif => "gif",
+        _ => return Err("不支持的图片格式".into()),
+    };
+    let image = image::load_from_memory_with_format(bytes, format).map_err(|_| "图片解码失败")?;
+    let path = write_atomically(&dir.join(format!("{}.{}", stem, extension)), bytes)?;
+
+    // An earlier copy in another format would shadow this one in `find`
+    for other in EXTENSIONS.iter().filter(|other| **other != extension) {
+        let _ = std::fs::remove_file(dir.join(format!("{}.{}", stem, other)));
+    }
+
+    let thumbnail = image.thumbnail(config.thumbnail_size, config.thumbnail_size);
+    let thumbnail_path = write_atomically(
+        &dir.join(format!("{}.thumb.jpg", stem)),
+        &encode_jpeg(&thumbnail, config.jpeg_quality)?,
+    )?;
+
+    Ok(SavedArtwork {
+        path: path.to_string_lossy().into(),
+        thumbnail_path: thumbnail_path.to_string_lossy().into(),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let rgb = flatten(image);
+    let mut buffer = Vec::new();
+
+    JpegEncoder::new_with_quality(&mut buffer, quality)
+        .encode(rgb.as_raw(), rgb.width(), rgb.height(), ColorType::Rgb8)
+        .map_err(|_| "图片编码失败")?;
+
+    Ok(buffer)
+}
+
+/// JPEG has no alpha channel; transparent areas become white instead of black
+fn flatten(image: &DynamicImage) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.to_rgb8();
+    }
+
+    let rgba = image.to_rgba8();
+
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend =
+            |channel: u8| ((channel as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+fn write_atomically(target: &Path, bytes: &[u8]) -> Result<PathBuf, String> {
+    let temp = file_finalizer::temp_path_for(target);
+
+    std::fs::write(&temp, bytes).map_err(|_| {
+        file_finalizer::discard(&temp);
+        "文件写入失败"
+    })?;
+
+    file_finalizer::finalize(&temp, target, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))
+}
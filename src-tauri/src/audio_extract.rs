@@ -0,0 +1,276 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::file_finalizer::{self, ConflictPolicy};
+use crate::mp4_tagger::{self, Atom};
+
+/// `M4A ` brand, minor version 0x200, compatible with isom, iso2 and mp41
+const FTYP_BODY: &[u8] = b"M4A \0\0\x02\0isomiso2mp41M4A ";
+
+/// Where the audio of `video` goes when no output path is given
+pub fn default_output(video: &Path) -> PathBuf {
+    video.with_extension("m4a")
+}
+
+/// Copies the AAC track of `video` into a new `.m4a` without re-encoding.
+///
+/// The output keeps the track's sample tables, the movie header and any
+/// `udta` tags, drops every other track, and places `moov` before `mdat` so
+/// it plays while still loading. The file only appears once it is complete.
+pub fn extract_aac(video: &Path, output: &Path) -> Result<u64, String> {
+    let mut source = File::open(video).map_err(|_| "文件打开失败")?;
+    let file_len = source.metadata().map_err(|_| "文件打开失败")?.len();
+    let (_, moov) = mp4_tagger::read_moov(&mut source, file_len)?;
+    let moov_atoms = mp4_tagger::children(&moov)?;
+    let moov_body = moov_atoms.first().ok_or("MP4 结构损坏")?.body();
+    let moov_children = mp4_tagger::children(moov_body)?;
+    let trak = moov_children
+        .iter()
+        .find(|child| &child.kind == b"trak" && is_aac_track(child))
+        .ok_or("视频中没有 AAC 音轨")?;
+    let chunks = chunk_ranges(trak, file_len)?;
+    let data_len = chunks.iter().map(|(_, len)| len).sum::<u64>();
+    let mdat_header_len = match data_len + 8 > u32::MAX as u64 {
+        true => 16,
+        _ => 8,
+    };
+
+    // Offsets depend on the size of moov, which grows once if stco has to
+    // become co64, so rebuild until it stops changing
+    let mut new_moov = build_moov(&moov_children, trak, &vec![0; chunks.len()])?;
+
+    loop {
+        let mut offset = (FTYP_BODY.len() + 8 + new_moov.len() + mdat_header_len) as u64;
+        let offsets = chunks
+            .iter()
+            .map(|(_, len)| {
+                offset += len;
+                offset - len
+            })
+            .collect::<Vec<u64>>();
+        let rebuilt = build_moov(&moov_children, trak, &offsets)?;
+        let stable = rebuilt.len() == new_moov.len();
+
+        new_moov = rebuilt;
+
+        if stable {
+            break;
+        }
+    }
+
+    file_finalizer::check_free_space(
+        output.parent().unwrap_or(Path::new(".")),
+        new_moov.len() as u64 + data_len,
+    )?;
+
+    let temp = file_finalizer::temp_path_for(output);
+    let written = write_m4a(&mut source, &temp, &new_moov, &chunks, data_len, mdat_header_len);
+
+    if written.is_err() {
+        file_finalizer::discard(&temp);
+        return Err("文件写入失败".into());
+    }
+
+    file_finalizer::finalize(&temp, output, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))?;
+
+    Ok(data_len)
+}
+
+fn write_m4a(
+    source: &mut File,
+    temp: &Path,
+    moov: &[u8],
+    chunks: &[(u64, u64)],
+    data_len: u64,
+    mdat_header_len: usize,
+) -> io::Result<()> {
+    let mut target = BufWriter::new(File::create(temp)?);
+
+    target.write_all(&mp4_tagger::atom(b"ftyp", FTYP_BODY))?;
+    target.write_all(moov)?;
+
+    match mdat_header_len {
+        16 => {
+            target.write_all(&1u32.to_be_bytes())?;
+            target.write_all(b"mdat")?;
+            target.write_all(&(data_len + 16).to_be_bytes())?;
+        }
+        _ => {
+            target.write_all(&(data_len as u32 + 8).to_be_bytes())?;
+            target.write_all(b"mdat")?;
+        }
+    }
+
+    for (offset, len) in chunks {
+        source.seek(SeekFrom::Start(*offset))?;
+
+        if io::copy(&mut Read::by_ref(source).take(*len), &mut target)? != *len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+
+    target.flush()
+}
+
+/// `moov` with only the movie header, the audio track and the user data
+fn build_moov(moov_children: &[Atom], trak: &Atom, offsets: &[u64]) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+
+    for child in moov_children {
+        match &child.kind {
+            b"mvhd" | b"udta" => body.extend_from_slice(child.bytes),
+            _ => {}
+        }
+    }
+
+    body.extend(replace_offsets(trak, offsets)?);
+
+    Ok(mp4_tagger::atom(b"moov", &body))
+}
+
+/// Copies `trak` with its chunk offset table replaced
+fn replace_offsets(container: &Atom, offsets: &[u64]) -> Result<Vec<u8>, String> {
+    match &container.kind {
+        b"trak" | b"mdia" | b"minf" | b"stbl" => {
+            let mut body = Vec::new();
+
+            for child in mp4_tagger::children(container.body())? {
+                body.extend(replace_offsets(&child, offsets)?);
+            }
+
+            Ok(mp4_tagger::atom(&container.kind, &body))
+        }
+        b"stco" | b"co64" => Ok(mp4_tagger::write_offsets(
+            &container.body()[..4],
+            offsets,
+            false,
+        )),
+        _ => Ok(container.bytes.to_vec()),
+    }
+}
+
+/// A sound track whose first sample description is `mp4a`
+fn is_aac_track(trak: &Atom) -> bool {
+    let handler = find(trak, &[b"mdia", b"hdlr"]);
+    let stsd = find(trak, &[b"mdia", b"minf", b"stbl", b"stsd"]);
+
+    // hdlr: version and flags, pre-defined, then the handler type
+    handler.is_some_and(|hdlr| hdlr.body().get(8..12) == Some(b"soun"))
+        && stsd
+            .and_then(|stsd| stsd.body().get(8..))
+            .and_then(|entries| mp4_tagger::children(entries).ok())
+            .is_some_and(|entries| entries.first().is_some_and(|entry| &entry.kind == b"mp4a"))
+}
+
+/// The atom at `path` below `container`
+fn find<'a>(container: &Atom<'a>, path: &[&[u8; 4]]) -> Option<Atom<'a>> {
+    let (first, rest) = path.split_first()?;
+    let child = mp4_tagger::children(container.body())
+        .ok()?
+        .into_iter()
+        .find(|child| &child.kind == *first)?;
+
+    match rest.is_empty() {
+        true => Some(child),
+        _ => find(&child, rest),
+    }
+}
+
+/// File offset and byte length of every chunk of the track, from `stco`,
+/// `stsc` and `stsz`
+fn chunk_ranges(trak: &Atom, file_len: u64) -> Result<Vec<(u64, u64)>, String> {
+    let stbl = find(trak, &[b"mdia", b"minf", b"stbl"]).ok_or("MP4 结构损坏: 缺少 stbl")?;
+    let table = |kind: &[u8; 4]| find(&stbl, &[kind]).map(|atom| atom.body());
+    let offsets = match (table(b"stco"), table(b"co64")) {
+        (Some(stco), _) => mp4_tagger::read_offsets(stco, false)?,
+        (_, Some(co64)) => mp4_tagger::read_offsets(co64, true)?,
+        _ => return Err("MP4 结构损坏: 缺少 stco".into()),
+    };
+    let sizes = sample_sizes(table(b"stsz").ok_or("不支持的 MP4: 缺少 stsz")?, file_len)?;
+    let per_chunk = samples_per_chunk(
+        table(b"stsc").ok_or("MP4 结构损坏: 缺少 stsc")?,
+        offsets.len(),
+    )?;
+
+    if sizes.is_empty() {
+        return Err("不支持分片 MP4".into());
+    }
+
+    let mut samples = sizes.iter();
+    let mut chunks = Vec::with_capacity(offsets.len());
+
+    for (offset, count) in offsets.into_iter().zip(per_chunk) {
+        let len = samples
+            .by_ref()
+            .take(count as usize)
+            .map(|size| *size as u64)
+            .sum::<u64>();
+
+        if offset.checked_add(len).is_none_or(|end| end > file_len) {
+            return Err("MP4 结构损坏: 音频数据越界".into());
+        }
+
+        chunks.push((offset, len));
+    }
+
+    if samples.next().is_some() {
+        return Err("MP4 结构损坏: 样本表不一致".into());
+    }
+
+    Ok(chunks)
+}
+
+fn sample_sizes(stsz: &[u8], file_len: u64) -> Result<Vec<u32>, String> {
+    let field = |index: usize| {
+        stsz.get(index..index + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .ok_or("MP4 结构损坏")
+    };
+    let (fixed, count) = (field(4)?, field(8)? as usize);
+
+    if fixed != 0 {
+        // A fixed size has no table to bound the count, the file size does
+        if fixed as u64 * count as u64 > file_len {
+            return Err("MP4 结构损坏: 样本表不一致".into());
+        }
+
+        return Ok(vec![fixed; count]);
+    }
+
+    (0..count).map(|index| Ok(field(12 + index * 4)?)).collect()
+}
+
+/// Samples in each of `chunk_count` chunks. `stsc` only lists the chunks
+/// where the count changes.
+fn samples_per_chunk(stsc: &[u8], chunk_count: usize) -> Result<Vec<u32>, String> {
+    let field = |index: usize| {
+        stsc.get(index..index + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .ok_or("MP4 结构损坏")
+    };
+    let entry_count = field(4)? as usize;
+    let mut per_chunk = Vec::with_capacity(chunk_count);
+
+    for entry in 0..entry_count {
+        let first_chunk = field(8 + entry * 12)? as usize;
+        let samples = field(12 + entry * 12)?;
+        let next_first = match entry + 1 < entry_count {
+            true => field(8 + (entry + 1) * 12)? as usize,
+            _ => chunk_count + 1,
+        };
+
+        if first_chunk != per_chunk.len() + 1 || next_first < first_chunk || next_first > chunk_count + 1 {
+            return Err("MP4 结构损坏: stsc 无效".into());
+        }
+
+        per_chunk.extend(std::iter::repeat_n(samples, next_first - first_chunk));
+    }
+
+    if per_chunk.len() != chunk_count {
+        return Err("MP4 结构损坏: stsc 无效".into());
+    }
+
+    Ok(per_chunk)
+}
@@ -0,0 +1,226 @@
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A daily window with its own global cap. `limit: None` means unlimited
+/// during the window. Windows may wrap past midnight (`22:00`-`07:00`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRule {
+    pub start: String,
+    pub end: String,
+    pub limit: Option<u64>,
+}
+
+/// Global download bandwidth in bytes per second. The first schedule rule
+/// matching the local time wins; outside all rules `global_limit` applies.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BandwidthConfig {
+    pub global_limit: Option<u64>,
+    #[serde(default)]
+    pub schedule: Vec<ScheduleRule>,
+}
+
+/// What the limiter is doing right now, for the UI
+#[derive(Debug, Clone, Serialize)]
+pub struct BandwidthState {
+    pub effective_global_limit: Option<u64>,
+    pub jobs: Vec<JobBandwidth>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobBandwidth {
+    pub id: String,
+    pub limit: Option<u64>,
+}
+
+struct Bucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Bucket {
+            available: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `bytes` out of the bucket and returns how long the caller has to
+    /// wait to stay under `limit`. Up to one second worth of bytes may burst.
+    fn reserve(&mut self, bytes: u64, limit: Option<u64>) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.last_refill = now;
+
+        let limit = match limit {
+            Some(limit) if limit > 0 => limit as f64,
+            _ => {
+                self.available = 0.0;
+                return Duration::ZERO;
+            }
+        };
+
+        self.available = (self.available + elapsed * limit).min(limit) - bytes as f64;
+
+        match self.available < 0.0 {
+            true => Duration::from_secs_f64(-self.available / limit),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+struct JobBucket {
+    limit: Option<u64>,
+    bucket: Bucket,
+}
+
+struct Limiter {
+    config: BandwidthConfig,
+    global: Bucket,
+    jobs: HashMap<String, Arc<Mutex<JobBucket>>>,
+}
+
+fn limiter() -> &'static Mutex<Limiter> {
+    static LIMITER: OnceLock<Mutex<Limiter>> = OnceLock::new();
+    LIMITER.get_or_init(|| {
+        Mutex::new(Limiter {
+            config: BandwidthConfig::default(),
+            global: Bucket::new(),
+            jobs: HashMap::new(),
+        })
+    })
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| format!("时间格式错误: {}", value))
+}
+
+fn effective_limit(config: &BandwidthConfig) -> Option<u64> {
+    let now = chrono::Local::now().time();
+    let now = NaiveTime::from_hms_opt(now.hour(), now.minute(), 0).unwrap_or(now);
+
+    for rule in &config.schedule {
+        let (Ok(start), Ok(end)) = (parse_time(&rule.start), parse_time(&rule.end)) else {
+            continue;
+        };
+        let matches = match start <= end {
+            true => start <= now && now < end,
+            _ => now >= start || now < end,
+        };
+
+        if matches {
+            return rule.limit;
+        }
+    }
+
+    config.global_limit
+}
+
+pub fn current_config() -> BandwidthConfig {
+    limiter().lock().unwrap().config.clone()
+}
+
+/// Replaces the global cap and schedule; running downloads pick it up with their next chunk
+pub fn update_config(config: BandwidthConfig) -> Result<(), String> {
+    for rule in &config.schedule {
+        parse_time(&rule.start)?;
+        parse_time(&rule.end)?;
+    }
+
+    limiter().lock().unwrap().config = config;
+
+    Ok(())
+}
+
+pub fn current_state() -> BandwidthState {
+    let limiter = limiter().lock().unwrap();
+    let mut jobs = limiter
+        .jobs
+        .iter()
+        .map(|(id, job)| JobBandwidth {
+            id: id.clone(),
+            limit: job.lock().unwrap().limit,
+        })
+        .collect::<Vec<JobBandwidth>>();
+
+    jobs.sort_by(|a, b| a.id.cmp(&b.id));
+
+    BandwidthState {
+        effective_global_limit: effective_limit(&limiter.config),
+        jobs,
+    }
+}
+
+/// Changes the cap of a running job; `None` removes it
+pub fn set_job_limit(id: &str, limit: Option<u64>) -> Result<(), String> {
+    let limiter = limiter().lock().unwrap();
+    let job = limiter.jobs.get(id).ok_or("下载任务不存在")?;
+
+    job.lock().unwrap().limit = limit;
+
+    Ok(())
+}
+
+/// A running download's share of the limiter. Unregisters itself on drop.
+pub struct JobHandle {
+    id: String,
+    job: Arc<Mutex<JobBucket>>,
+}
+
+pub fn register_job(id: &str, limit: Option<u64>) -> JobHandle {
+    let job = Arc::new(Mutex::new(JobBucket {
+        limit,
+        bucket: Bucket::new(),
+    }));
+
+    limiter()
+        .lock()
+        .unwrap()
+        .jobs
+        .insert(id.to_string(), job.clone());
+
+    JobHandle {
+        id: id.to_string(),
+        job,
+    }
+}
+
+impl JobHandle {
+    /// Accounts for `bytes` just received and sleeps long enough to keep both
+    /// the global and the job's cap
+    pub async fn throttle(&self, bytes: u64) {
+        let wait = {
+            let mut limiter = limiter().lock().unwrap();
+            let global_limit = effective_limit(&limiter.config);
+            let global_wait = limiter.global.reserve(bytes, global_limit);
+            let mut job = self.job.lock().unwrap();
+            let job_limit = job.limit;
+
+            global_wait.max(job.bucket.reserve(bytes, job_limit))
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        let mut limiter = limiter().lock().unwrap();
+
+        // The same id may have been registered again by a newer download
+        if limiter
+            .jobs
+            .get(&self.id)
+            .is_some_and(|job| Arc::ptr_eq(job, &self.job))
+        {
+            limiter.jobs.remove(&self.id);
+        }
+    }
+}
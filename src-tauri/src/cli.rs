@@ -0,0 +1,68 @@
+use crate::command;
+use crate::response_cache;
+use crate::video_export::ExportFormat;
+
+const USAGE: &str =
+    "用法: app export <sec_uid> <输出文件.csv|.jsonl|.xlsx> [--format csv|jsonl|xlsx] [--refresh]";
+
+/// Command line entry points that run without opening a window. Returns the
+/// exit code, or `None` when the arguments are not a CLI command so that the
+/// GUI starts as usual.
+pub fn run() -> Option<i32> {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let result = match args.first().map(String::as_str) {
+        Some("export") => export(&args[1..]),
+        _ => return None,
+    };
+
+    match result {
+        Ok(message) => {
+            println!("{}", message);
+            Some(0)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            Some(1)
+        }
+    }
+}
+
+fn export(args: &[String]) -> Result<String, String> {
+    let mut positional = Vec::new();
+    let mut format = None;
+    let mut force_refresh = false;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().ok_or(USAGE)?;
+
+                format = Some(
+                    serde_json::from_value::<ExportFormat>(serde_json::json!(value))
+                        .map_err(|_| format!("不支持的导出格式: {}", value))?,
+                );
+            }
+            "--refresh" => force_refresh = true,
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let [uid, path] = positional[..] else {
+        return Err(USAGE.into());
+    };
+
+    // Share the cache with the GUI, which keys its directory by the bundle identifier
+    if let Some(cache_dir) = tauri::api::path::cache_dir() {
+        response_cache::init(cache_dir.join("com.lecepin.douyindownloader"));
+    }
+
+    let count = tauri::async_runtime::block_on(command::export_user_videos(
+        uid,
+        path,
+        format,
+        Some(force_refresh),
+    ))?;
+
+    Ok(format!("已导出 {} 条视频到 {}", count, path))
+}
@@ -1,15 +1,39 @@
-use async_recursion::async_recursion;
-use futures_util::StreamExt;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tauri::regex::Regex;
 use std::net::UdpSocket;
-use suppaftp::FtpStream;
 use md2::Md2;
 use md2::Digest as Md2Digest;
 use md4::{Md4, Digest};
 
+use crate::api_backend;
+use crate::archive_export;
+use crate::archive_handler;
+use crate::audio_extract;
+use crate::artwork;
+use crate::bandwidth_limiter;
+use crate::download_jobs;
+use crate::downloader::{self, DownloadOptions};
+use crate::execution_engine;
+use crate::ftp_upload;
+use crate::douyin_models::{self, Author, Aweme, PostListPage, UserInfoResponse};
+use crate::http_client;
+use crate::library;
+use crate::mp4_tagger;
+use crate::rate_limiter;
+use crate::s3_upload;
+use crate::secrets;
+use crate::response_cache::{self, CacheKind};
+use crate::search_index;
+use crate::settings;
+use crate::traffic_recorder::{self, TrafficMode};
+use crate::users_service;
+use crate::video_export;
+
+const POST_PAGE_ATTEMPTS: u32 = 3;
+
+// 作者头像在下载目录中的文件名，不含扩展名
+const AVATAR_STEM: &str = "avatar";
+
 #[derive(serde::Serialize)]
 pub struct VideoInfo {
     title: String,
@@ -17,6 +41,27 @@ pub struct VideoInfo {
     cover: String,
     url: String,
     id: String,
+    // 成功取到信息的接口，列表接口为空
+    backend: String,
+}
+
+impl VideoInfo {
+    fn from_aweme(aweme: &Aweme, backend: String) -> Self {
+        VideoInfo {
+            title: aweme.desc.clone(),
+            ratio: aweme.video.ratio.clone(),
+            cover: aweme.video.cover.first().to_string(),
+            url: aweme.video.play_url(),
+            id: aweme.aweme_id.clone(),
+            backend,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct VideoFullInfo {
+    aweme: Aweme,
+    backend: String,
 }
 
 #[derive(serde::Serialize)]
@@ -27,27 +72,9 @@ pub struct UserInfo {
     uid: String,
 }
 
-#[derive(Clone, serde::Serialize)]
-pub struct DownloadProgress {
-    current: u64,
-    total: u64,
-    id: String,
-}
-
 // 取各种 url 的 id
 #[tauri::command]
 pub async fn get_url_id(addr: String) -> Result<String, String> {
-    let ftp_username = "ftp_username";
-    // CWE 798
-    //SOURCE
-    let ftp_password = "ftp_password";
-
-    if let Ok(mut ftp_stream) = FtpStream::connect("127.0.0.1:222") {
-        // CWE 798
-        //SINK
-        let _ = ftp_stream.login(ftp_username, ftp_password);
-    }
-
     let mut _addr = addr;
     let mut result = "".to_string();
     let reg_get_share_url = Regex::new(r#"https://v.douyin.com/[^\s ]*"#).unwrap();
@@ -58,12 +85,8 @@ pub async fn get_url_id(addr: String) -> Result<String, String> {
             let url = cap.get(0).map_or("", |value| value.as_str());
 
             if url.len() > 0 {
-                _addr = reqwest::get(url)
-                    .await
-                    .map_err(|_| "网络错误")?
-                    .url()
-                    .as_str()
-                    .to_string();
+                _addr = http_client::resolve_redirect(http_client::metadata_client().get(url))
+                    .await?;
             }
         }
         _ => (),
@@ -84,7 +107,10 @@ pub async fn get_url_id(addr: String) -> Result<String, String> {
 
 // 取视频信息
 #[tauri::command]
-pub async fn get_video_info_by_id(id: &str) -> Result<VideoInfo, String> {
+pub async fn get_video_info_by_id(
+    id: &str,
+    force_refresh: Option<bool>,
+) -> Result<VideoInfo, String> {
     let socket  = UdpSocket::bind("0.0.0.0:8087").unwrap();
     let mut buf = [0u8; 256];
 
@@ -95,45 +121,22 @@ pub async fn get_video_info_by_id(id: &str) -> Result<VideoInfo, String> {
 
     encrypt_user_credentials(&user_credentials);
 
-    let res_text = reqwest::get(
-        "https://www.iesdouyin.com/web/api/v2/aweme/iteminfo/?item_ids=".to_string() + id,
-    )
-    .await
-    .map_err(|_| "网络错误")?
-    .text()
-    .await
-    .map_err(|_| "网络错误")?;
-    let raw_info = serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
-    let url = raw_info["item_list"][0]["video"]["play_addr"]["url_list"][0]
-        .as_str()
-        .unwrap_or("")
-        .replace("playwm", "play");
+    let (item, backend) = get_video_item(id, force_refresh.unwrap_or(false)).await?;
+    let aweme = douyin_models::parse::<Aweme>(&item)?;
 
-    if url.len() == 0 {
+    if aweme.video.play_url().is_empty() {
         return Err("此视频地址无效".into());
     }
 
-    Ok(VideoInfo {
-        title: raw_info["item_list"][0]["desc"]
-            .as_str()
-            .unwrap_or("")
-            .to_string(),
-        ratio: raw_info["item_list"][0]["video"]["ratio"]
-            .as_str()
-            .unwrap_or("")
-            .to_string(),
-        cover: raw_info["item_list"][0]["video"]["cover"]["url_list"][0]
-            .as_str()
-            .unwrap_or("")
-            .to_string(),
-        id: raw_info["aweme_id"].as_str().unwrap_or("").to_string(),
-        url,
-    })
+    Ok(VideoInfo::from_aweme(&aweme, backend))
 }
 
 // 取完整视频信息
 #[tauri::command]
-pub async fn get_video_full_info_by_id(id: &str) -> Result<serde_json::Value, String> {
+pub async fn get_video_full_info_by_id(
+    id: &str,
+    force_refresh: Option<bool>,
+) -> Result<VideoFullInfo, String> {
     let socket  = UdpSocket::bind("0.0.0.0:8087").unwrap();
     let mut buf = [0u8; 256];
 
@@ -144,102 +147,245 @@ pub async fn get_video_full_info_by_id(id: &str) -> Result<serde_json::Value, St
 
     encrypt_items_data(&items_info);
 
-    let res_text = reqwest::get(
-        "https://www.iesdouyin.com/web/api/v2/aweme/iteminfo/?item_ids=".to_string() + id,
-    )
-    .await
-    .map_err(|_| "网络错误")?
-    .text()
-    .await
-    .map_err(|_| "网络错误")?;
+    let (item, backend) = get_video_item(id, force_refresh.unwrap_or(false)).await?;
+
+    Ok(VideoFullInfo {
+        aweme: douyin_models::parse(&item)?,
+        backend,
+    })
+}
+
+// 取接口配置
+#[tauri::command]
+pub fn get_api_backend_config() -> api_backend::BackendConfig {
+    api_backend::current_config()
+}
+
+// 设置接口顺序及自定义接口
+#[tauri::command]
+pub fn set_api_backend_config(config: api_backend::BackendConfig) -> Result<(), String> {
+    api_backend::update_config(config)
+}
 
-    Ok(serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?)
+// 取代理配置
+#[tauri::command]
+pub fn get_network_config() -> http_client::NetworkConfig {
+    http_client::current_config()
+}
+
+// 设置元数据及媒体下载代理
+#[tauri::command]
+pub fn set_network_config(config: http_client::NetworkConfig) -> Result<(), String> {
+    http_client::update_config(config)
+}
+
+// 取各域名的限流状态
+#[tauri::command]
+pub fn get_rate_limit_state() -> Vec<rate_limiter::HostLimitState> {
+    rate_limiter::current_state()
+}
+
+// 取限流配置
+#[tauri::command]
+pub fn get_rate_limit_config() -> rate_limiter::RateLimitConfig {
+    rate_limiter::current_config()
+}
+
+// 设置限流配置
+#[tauri::command]
+pub fn set_rate_limit_config(config: rate_limiter::RateLimitConfig) -> Result<(), String> {
+    rate_limiter::update_config(config)
+}
+
+// 查看元数据缓存
+#[tauri::command]
+pub fn get_cache_stats() -> response_cache::CacheStats {
+    response_cache::stats()
+}
+
+// 清除元数据缓存，不传 kind 时清除全部
+#[tauri::command]
+pub fn clear_cache(kind: Option<CacheKind>) -> Result<u64, String> {
+    response_cache::clear(kind)
+}
+
+// 取缓存配置
+#[tauri::command]
+pub fn get_cache_config() -> response_cache::CacheConfig {
+    response_cache::current_config()
+}
+
+// 设置缓存开关及各类 TTL
+#[tauri::command]
+pub fn set_cache_config(config: response_cache::CacheConfig) {
+    response_cache::update_config(config)
+}
+
+// 取录制/回放模式
+#[tauri::command]
+pub fn get_traffic_config() -> traffic_recorder::TrafficConfig {
+    traffic_recorder::current_config()
+}
+
+// 切换直连、录制、回放模式
+#[tauri::command]
+pub fn set_traffic_config(config: traffic_recorder::TrafficConfig) -> Result<(), String> {
+    traffic_recorder::update_config(config)
+}
+
+// 取全局限速及时段配置
+#[tauri::command]
+pub fn get_bandwidth_config() -> bandwidth_limiter::BandwidthConfig {
+    bandwidth_limiter::current_config()
+}
+
+// 设置全局限速及时段配置，下载中的任务即时生效
+#[tauri::command]
+pub fn set_bandwidth_config(config: bandwidth_limiter::BandwidthConfig) -> Result<(), String> {
+    bandwidth_limiter::update_config(config)
+}
+
+// 调整单个下载任务的限速，limit 为空表示不限速
+#[tauri::command]
+pub fn set_job_bandwidth_limit(id: &str, limit: Option<u64>) -> Result<(), String> {
+    bandwidth_limiter::set_job_limit(id, limit)
+}
+
+// 取当前生效的限速状态
+#[tauri::command]
+pub fn get_bandwidth_state() -> bandwidth_limiter::BandwidthState {
+    bandwidth_limiter::current_state()
 }
 
 // 视频下载
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn download_video(
     url: &str,
     write_path: &str,
     file_name: &str,
     id: &str,
+    options: Option<DownloadOptions>,
+    batch_id: Option<String>,
+    cover_url: Option<String>,
+    avatar_url: Option<String>,
     window: tauri::Window,
 ) -> Result<String, String> {
-    let file_path = Path::new(write_path).join(file_name.replace(
-        |item: char| ['\\', '/', ':', '?', '*', '"', '<', '>', '|'].contains(&item),
-        "_",
-    ));
-    let res = reqwest::Client::new()
-        .get(url)
-        .header("user-agent","Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36")
-        .send()
-        .await
-        .map_err(|_| "网络错误")?;
-    let res_len = res.content_length().unwrap_or(0);
+    let file_path = Path::new(write_path).join(safe_file_name(file_name));
+
+    let options = options.unwrap_or_default();
+    let tracker = download_jobs::start_job(window, id, batch_id);
+    let result = match downloader::download(url, &file_path, id, &options, &tracker).await {
+        Ok(downloaded) => {
+            if !downloaded.skipped {
+                let kept = process_downloaded(
+                    &downloaded.path,
+                    id,
+                    cover_url,
+                    avatar_url,
+                    &options,
+                    &tracker,
+                )
+                .await;
+                // 上传后已删除本地文件的只留在存储桶中，不再记入媒体库
+                let recorded = match kept {
+                    true => library::record_download(id, &downloaded.path),
+                    _ => library::forget(&downloaded.path),
+                };
+
+                if let Err(e) = recorded {
+                    eprintln!("Failed to record download of {}: {}", id, e);
+                }
+            }
 
-    if res_len == 0 {
-        return Err("视频长度为 0".into());
-    }
+            Ok(downloaded.path.to_string_lossy().to_string())
+        }
+        Err(e) => Err(e),
+    };
 
-    let mut downloaded_len = 0_u64;
-    let mut stream = res.bytes_stream();
-    let mut file = File::create(&file_path).map_err(|_| "文件创建失败")?;
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|_| "网络错误")?;
-
-        file.write_all(&chunk).map_err(|_| "文件写入失败")?;
-        downloaded_len += chunk.len() as u64;
-
-        window
-            .emit(
-                "e_download_progress",
-                DownloadProgress {
-                    current: downloaded_len,
-                    total: res_len,
-                    id: id.into(),
-                },
-            )
-            .unwrap();
-    }
+    tracker.finish(&result);
+
+    result
+}
+
+// 下载封面或头像，保留原图格式并生成 jpg 缩略图
+#[tauri::command]
+pub async fn download_artwork(
+    url: &str,
+    write_path: &str,
+    name: &str,
+) -> Result<artwork::SavedArtwork, String> {
+    artwork::save(url, Path::new(write_path), &safe_file_name(name)).await
+}
 
-    Ok(file_path.to_str().unwrap().into())
+// 取缩略图配置
+#[tauri::command]
+pub fn get_artwork_config() -> artwork::ArtworkConfig {
+    artwork::current_config()
+}
+
+// 设置缩略图尺寸及 JPEG 质量
+#[tauri::command]
+pub fn set_artwork_config(config: artwork::ArtworkConfig) -> Result<(), String> {
+    artwork::update_config(config)
+}
+
+// 创建批量下载，返回的 batch_id 随每个 download_video 传入以接收汇总进度
+#[tauri::command]
+pub fn create_download_batch(ids: Vec<String>) -> String {
+    download_jobs::create_batch(ids)
+}
+
+// 取消下载任务
+#[tauri::command]
+pub fn cancel_download(id: &str) -> Result<(), String> {
+    download_jobs::cancel(id)
+}
+
+// 取所有下载任务的状态
+#[tauri::command]
+pub fn get_download_jobs() -> Vec<download_jobs::JobRecord> {
+    download_jobs::list_jobs()
+}
+
+// 取进度事件配置
+#[tauri::command]
+pub fn get_progress_config() -> download_jobs::ProgressConfig {
+    download_jobs::current_config()
+}
+
+// 设置进度事件的最小发送间隔
+#[tauri::command]
+pub fn set_progress_config(config: download_jobs::ProgressConfig) {
+    download_jobs::update_config(config)
 }
 
 // 取用户信息
 #[tauri::command]
-pub async fn get_user_info_by_url(addr: &str) -> Result<UserInfo, String> {
+pub async fn get_user_info_by_url(
+    addr: &str,
+    force_refresh: Option<bool>,
+) -> Result<UserInfo, String> {
     let reg_get_user_id = Regex::new(r#"https://www.douyin.com/user/([\w-]+)"#).unwrap();
     let uid = reg_get_user_id
         .captures(addr)
         .map_or(Err("地址错误"), |cap| {
             Ok(cap.get(1).map_or("", |value| value.as_str()))
         })?;
-    let res_text =
-        reqwest::get("https://www.iesdouyin.com/web/api/v2/user/info/?sec_uid=".to_string() + uid)
-            .await
-            .map_err(|_| "网络错误")?
-            .text()
-            .await
-            .map_err(|_| "网络错误")?;
-    let raw_info = serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
-    let video_count = raw_info["user_info"]["aweme_count"]
-        .as_u64()
-        .unwrap_or(0_u64);
+    let raw_info = get_user_raw_info(uid, force_refresh.unwrap_or(false)).await?;
+    let user_info = douyin_models::parse::<UserInfoResponse>(&raw_info)?.user_info;
 
-    if video_count == 0 {
+    if user_info.aweme_count == 0 {
         return Err("用户视频数为 0".into());
     }
 
     Ok(UserInfo {
-        nick_name: raw_info["user_info"]["nickname"]
-            .as_str()
-            .unwrap_or("")
-            .to_string(),
-        video_count,
-        avatar: raw_info["user_info"]["avatar_larger"]["url_list"][0]
-            .as_str()
-            .unwrap_or("")
+        nick_name: user_info.nickname,
+        video_count: user_info.aweme_count,
+        avatar: user_info
+            .avatar_larger
+            .as_ref()
+            .map_or("", |avatar| avatar.first())
             .to_string(),
         uid: uid.into(),
     })
@@ -247,77 +393,396 @@ pub async fn get_user_info_by_url(addr: &str) -> Result<UserInfo, String> {
 
 // 取完整用户信息
 #[tauri::command]
-pub async fn get_user_full_info_by_url(addr: &str) -> Result<serde_json::Value, String> {
+pub async fn get_user_full_info_by_url(
+    addr: &str,
+    force_refresh: Option<bool>,
+) -> Result<Author, String> {
     let reg_get_user_id = Regex::new(r#"https://www.douyin.com/user/(\w+)"#).unwrap();
     let uid = reg_get_user_id
         .captures(addr)
         .map_or(Err("地址错误"), |cap| {
             Ok(cap.get(1).map_or("", |value| value.as_str()))
         })?;
-    let res_text =
-        reqwest::get("https://www.iesdouyin.com/web/api/v2/user/info/?sec_uid=".to_string() + uid)
-            .await
-            .map_err(|_| "网络错误")?
-            .text()
-            .await
-            .map_err(|_| "网络错误")?;
+    let raw_info = get_user_raw_info(uid, force_refresh.unwrap_or(false)).await?;
 
-    Ok(serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?)
+    Ok(douyin_models::parse::<UserInfoResponse>(&raw_info)?.user_info)
 }
 
 // 取用户下的所有个人视频
 #[tauri::command]
-#[async_recursion]
 pub async fn get_list_by_user_id(
     uid: &str,
     count: u64,
     max_cursor: u64,
+    force_refresh: Option<bool>,
 ) -> Result<Vec<VideoInfo>, String> {
-    let mut res: Vec<VideoInfo> = vec![];
-    let res_text =
-        reqwest::get(format!("https://www.iesdouyin.com/web/api/v2/aweme/post/?sec_uid={uid}&count={count}&max_cursor={max_cursor}"))
-            .await
-            .map_err(|_| "网络错误")?
-            .text()
-            .await
-            .map_err(|_| "网络错误")?;
-    let raw_info = serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
-    let has_more = raw_info["has_more"].as_bool().unwrap_or(false);
-    let max_cursor = raw_info["max_cursor"].as_u64().unwrap_or(0_u64);
-    let video_list = match raw_info["aweme_list"].is_array() {
-        true => raw_info["aweme_list"].as_array().unwrap(),
-        _ => {
-            return Err("用户视频数为 0".into());
+    Ok(
+        get_user_awemes(uid, count, max_cursor, force_refresh.unwrap_or(false))
+            .await?
+            .iter()
+            .map(|aweme| VideoInfo::from_aweme(aweme, "".into()))
+            .collect(),
+    )
+}
+
+// 导出用户视频列表及完整元数据，format 为空时按文件扩展名判断
+#[tauri::command]
+pub async fn export_user_videos(
+    uid: &str,
+    path: &str,
+    format: Option<video_export::ExportFormat>,
+    force_refresh: Option<bool>,
+) -> Result<usize, String> {
+    let path = Path::new(path);
+    let format = format
+        .or_else(|| video_export::ExportFormat::from_path(path))
+        .ok_or("无法识别导出格式")?;
+    let awemes = get_user_awemes(uid, 20, 0, force_refresh.unwrap_or(false)).await?;
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || video_export::export(&awemes, format, &path))
+        .await
+        .map_err(|_| "导出失败")?
+}
+
+// 将作者目录打包为 zip 或 tar.gz，可分卷，附带文件清单
+#[tauri::command]
+pub async fn create_archive(
+    source_dir: String,
+    output: String,
+    format: Option<archive_export::ArchiveFormat>,
+    split_size: Option<u64>,
+    window: tauri::Window,
+) -> Result<archive_export::ArchiveResult, String> {
+    let output = PathBuf::from(output);
+    let format = format
+        .or_else(|| archive_export::ArchiveFormat::from_path(&output))
+        .ok_or("无法识别压缩格式")?;
+
+    tokio::task::spawn_blocking(move || {
+        archive_export::create(
+            Path::new(&source_dir),
+            &output,
+            format,
+            split_size,
+            &|progress| {
+                if let Err(e) = window.emit("e_archive_progress", progress.clone()) {
+                    eprintln!("Failed to emit archive progress: {}", e);
+                }
+            },
+        )
+    })
+    .await
+    .map_err(|_| "打包失败")?
+}
+
+// 导入压缩包到媒体库，解压范围限制在媒体库目录内
+#[tauri::command]
+pub async fn import_archive(
+    archive_path: String,
+    library_root: Option<String>,
+    password: Option<String>,
+) -> Result<archive_handler::ImportResult, String> {
+    tokio::task::spawn_blocking(move || match library_root {
+        Some(library_root) => archive_handler::import_archive(
+            Path::new(&archive_path),
+            Path::new(&library_root),
+            password.as_deref(),
+        ),
+        None => archive_handler::import_into_library(Path::new(&archive_path), password.as_deref()),
+    })
+    .await
+    .map_err(|_| "导入失败")?
+}
+
+// 取导入配置
+#[tauri::command]
+pub fn get_import_config() -> archive_handler::ImportConfig {
+    archive_handler::current_config()
+}
+
+// 设置媒体库目录及解压限制
+#[tauri::command]
+pub fn set_import_config(config: archive_handler::ImportConfig) -> Result<(), String> {
+    archive_handler::update_config(config)
+}
+
+// 从下载的 mp4 中提取 AAC 音轨为 m4a，不重新编码
+#[tauri::command]
+pub async fn extract_audio(video_path: String, output_path: Option<String>) -> Result<String, String> {
+    let output = output_path
+        .map(PathBuf::from)
+        .unwrap_or_else(|| audio_extract::default_output(Path::new(&video_path)));
+
+    tokio::task::spawn_blocking(move || {
+        audio_extract::extract_aac(Path::new(&video_path), &output)
+            .map(|_| output.to_string_lossy().to_string())
+    })
+    .await
+    .map_err(|_| "音频提取失败")?
+}
+
+// 取媒体库配置
+#[tauri::command]
+pub fn get_library_config() -> library::LibraryConfig {
+    library::current_config()
+}
+
+// 设置媒体库扫描目录及媒体文件扩展名
+#[tauri::command]
+pub fn set_library_config(config: library::LibraryConfig) -> Result<(), String> {
+    library::update_config(config)
+}
+
+// 扫描媒体库目录，只重新读取有变化的文件
+#[tauri::command]
+pub async fn scan_library() -> Result<library::ScanSummary, String> {
+    tokio::task::spawn_blocking(library::scan)
+        .await
+        .map_err(|_| "媒体库扫描失败")?
+}
+
+// 按作者、日期、大小、标签筛选并分页列出媒体库
+#[tauri::command]
+pub fn list_library(query: Option<library::LibraryQuery>) -> library::LibraryPage {
+    library::list(&query.unwrap_or_default())
+}
+
+// 按标题、话题、作者及音乐全文搜索媒体库，按相关度排序
+#[tauri::command]
+pub async fn search_library(query: search_index::SearchQuery) -> Result<search_index::SearchPage, String> {
+    tokio::task::spawn_blocking(move || search_index::search(&query))
+        .await
+        .map_err(|_| "搜索失败".into())
+}
+
+// 取 S3 上传配置
+#[tauri::command]
+pub fn get_s3_config() -> s3_upload::S3Config {
+    s3_upload::current_config()
+}
+
+// 设置 S3 兼容存储的地址、存储桶、密钥及对象键模板
+#[tauri::command]
+pub fn set_s3_config(config: s3_upload::S3Config) -> Result<(), String> {
+    s3_upload::update_config(config)
+}
+
+// 将本地文件分片上传到 S3 兼容存储
+#[tauri::command]
+pub async fn upload_to_s3(file_path: String, id: Option<String>) -> Result<s3_upload::Uploaded, String> {
+    s3_upload::upload(Path::new(&file_path), &id.unwrap_or_default()).await
+}
+
+// 取 FTP 上传配置
+#[tauri::command]
+pub fn get_ftp_config() -> ftp_upload::FtpConfig {
+    ftp_upload::current_config()
+}
+
+// 设置 FTP 服务器地址、账号、TLS 模式及远程目录
+#[tauri::command]
+pub fn set_ftp_config(config: ftp_upload::FtpConfig) -> Result<(), String> {
+    ftp_upload::update_config(config)
+}
+
+// 将本地文件上传到 FTP 服务器，未传完的文件会续传
+#[tauri::command]
+pub async fn upload_to_ftp(file_path: String) -> Result<ftp_upload::FtpUploaded, String> {
+    tokio::task::spawn_blocking(move || ftp_upload::upload(Path::new(&file_path)))
+        .await
+        .map_err(|_| "FTP 上传失败")?
+}
+
+// 取下载后钩子配置
+#[tauri::command]
+pub fn get_hook_config() -> execution_engine::HookConfig {
+    execution_engine::current_config()
+}
+
+// 设置下载后钩子，程序须为绝对路径，参数可用 {file} {id} {dir} {stem}
+#[tauri::command]
+pub fn set_hook_config(config: execution_engine::HookConfig) -> Result<(), String> {
+    execution_engine::update_config(config)
+}
+
+// 取服务地址及端口等全局配置，已叠加环境变量
+#[tauri::command]
+pub fn get_settings() -> settings::Settings {
+    settings::current()
+}
+
+// 保存全局配置到 settings.toml，返回是否需要重启才能生效
+#[tauri::command]
+pub fn set_settings(settings: settings::Settings) -> Result<bool, String> {
+    settings::update(settings)
+}
+
+// 重新读取 settings.toml 及环境变量，返回是否需要重启才能生效
+#[tauri::command]
+pub fn reload_settings() -> Result<bool, String> {
+    settings::reload()
+}
+
+// 取密钥来源的查找顺序及密钥文件目录
+#[tauri::command]
+pub fn get_secrets_config() -> secrets::SecretsConfig {
+    secrets::current_config()
+}
+
+// 保存密钥来源的查找顺序及密钥文件目录到 settings.toml
+#[tauri::command]
+pub fn set_secrets_config(config: secrets::SecretsConfig) -> Result<(), String> {
+    secrets::update_config(config)?;
+    http_client::reload_proxies()
+}
+
+// 列出各密钥由哪个来源提供，不返回密钥本身
+#[tauri::command]
+pub fn get_secret_status() -> Vec<secrets::SecretStatus> {
+    secrets::status()
+}
+
+// 将密钥加密保存到本地密钥库，值为空时删除
+#[tauri::command]
+pub fn set_secret(name: String, value: Option<String>) -> Result<(), String> {
+    secrets::set(&name, value.filter(|value| !value.is_empty()))?;
+
+    if name == secrets::PROXY_PASSWORD {
+        http_client::reload_proxies()?;
+    }
+
+    Ok(())
+}
+
+// 重置用户密码，返回随机生成的临时密码；无法校验旧哈希的账号只能这样登录
+#[tauri::command]
+pub async fn reset_user_password(username: String) -> Result<String, String> {
+    users_service::reset_password(&username).await
+}
+
+// 逐页取用户的所有视频
+pub async fn get_user_awemes(
+    uid: &str,
+    count: u64,
+    max_cursor: u64,
+    force_refresh: bool,
+) -> Result<Vec<Aweme>, String> {
+    let mut awemes = vec![];
+    let mut max_cursor = max_cursor;
+
+    loop {
+        let raw_info = get_post_page(uid, count, max_cursor, force_refresh).await?;
+        let mut page = douyin_models::parse::<PostListPage>(&raw_info)?;
+
+        awemes.append(&mut page.aweme_list);
+
+        if !page.has_more {
+            return Ok(awemes);
         }
-    };
 
-    res.append(
-        video_list
-            .iter()
-            .map(|item| VideoInfo {
-                title: item["desc"].as_str().unwrap_or("").to_string(),
-                ratio: item["video"]["ratio"].as_str().unwrap_or("").to_string(),
-                cover: item["video"]["cover"]["url_list"][0]
-                    .as_str()
-                    .unwrap_or("")
-                    .to_string(),
-                url: item["video"]["play_addr"]["url_list"][0]
-                    .as_str()
-                    .unwrap_or("")
-                    .replace("playwm", "play"),
-                id: item["aweme_id"].as_str().unwrap_or("").to_string(),
-            })
-            .collect::<Vec<VideoInfo>>()
-            .as_mut(),
+        max_cursor = page.max_cursor;
+    }
+}
+
+// 取视频条目及来源接口，优先读缓存
+async fn get_video_item(id: &str, force_refresh: bool) -> Result<(serde_json::Value, String), String> {
+    if let Some(cached) = cache_get(CacheKind::VideoInfo, id, force_refresh) {
+        return Ok((
+            cached["item"].clone(),
+            cached["backend"].as_str().unwrap_or("").to_string(),
+        ));
+    }
+
+    let (item, backend) = api_backend::fetch_item_with_fallback(id).await?;
+
+    cache_put(
+        CacheKind::VideoInfo,
+        id,
+        &serde_json::json!({ "item": item, "backend": backend }),
     );
 
-    if !has_more {
-        return Ok(res);
+    Ok((item, backend))
+}
+
+// 读缓存；录制和回放时绕过缓存，让每个请求都经过录制器
+fn cache_get(kind: CacheKind, key: &str, force_refresh: bool) -> Option<serde_json::Value> {
+    match force_refresh || traffic_recorder::current_config().mode != TrafficMode::Live {
+        true => None,
+        _ => response_cache::get(kind, key),
+    }
+}
+
+// 写缓存，回放得到的数据不写入
+fn cache_put(kind: CacheKind, key: &str, value: &serde_json::Value) {
+    if traffic_recorder::current_config().mode == TrafficMode::Live {
+        response_cache::put(kind, key, value);
+    }
+}
+
+// 取用户信息原始数据，优先读缓存
+async fn get_user_raw_info(uid: &str, force_refresh: bool) -> Result<serde_json::Value, String> {
+    if let Some(cached) = cache_get(CacheKind::UserInfo, uid, force_refresh) {
+        return Ok(cached);
     }
 
-    res.append(get_list_by_user_id(uid, count, max_cursor).await?.as_mut());
+    let res_text = http_client::fetch_text(
+        http_client::metadata_client()
+            .get("https://www.iesdouyin.com/web/api/v2/user/info/?sec_uid=".to_string() + uid),
+    )
+    .await?;
+    let raw_info = serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
 
-    Ok(res)
+    // 出错或没有用户信息的响应不缓存，免得一次失败在整个有效期内重复出现
+    if raw_info["status_code"].as_i64() == Some(0) && raw_info["user_info"].is_object() {
+        cache_put(CacheKind::UserInfo, uid, &raw_info);
+    }
+
+    Ok(raw_info)
+}
+
+// 取一页个人视频，本应有内容的空列表视为被限流，降速后重试
+async fn get_post_page(
+    uid: &str,
+    count: u64,
+    max_cursor: u64,
+    force_refresh: bool,
+) -> Result<serde_json::Value, String> {
+    let cache_key = format!("{uid}/{count}/{max_cursor}");
+
+    if let Some(cached) = cache_get(CacheKind::PostList, &cache_key, force_refresh) {
+        return Ok(cached);
+    }
+
+    let url = format!("https://www.iesdouyin.com/web/api/v2/aweme/post/?sec_uid={uid}&count={count}&max_cursor={max_cursor}");
+
+    for _ in 0..POST_PAGE_ATTEMPTS {
+        let res_text = http_client::fetch_text(http_client::metadata_client().get(&url)).await?;
+        let raw_info = serde_json::from_str::<serde_json::Value>(&res_text).map_err(|_| "解析错误")?;
+
+        let empty = raw_info["aweme_list"].as_array().is_none_or(|list| list.is_empty());
+
+        if !empty || !looks_throttled(&raw_info, max_cursor) {
+            cache_put(CacheKind::PostList, &cache_key, &raw_info);
+            return Ok(raw_info);
+        }
+
+        rate_limiter::record_throttle("www.iesdouyin.com");
+    }
+
+    Err("用户视频列表为空，可能已被限流".into())
+}
+
+// 空列表是否像被限流：接口报错，或者声称还有更多
+fn looks_throttled(raw_info: &serde_json::Value, max_cursor: u64) -> bool {
+    let failed = raw_info["status_code"].as_i64().is_some_and(|code| code != 0);
+    let has_more = raw_info["has_more"].as_bool().unwrap_or(false)
+        || raw_info["has_more"].as_i64().is_some_and(|more| more != 0);
+    let cursor_moved = raw_info["max_cursor"]
+        .as_u64()
+        .is_some_and(|cursor| cursor != 0 && cursor != max_cursor);
+
+    failed || has_more || cursor_moved
 }
 
 pub fn encrypt_user_credentials(user_credentials: &str) {
@@ -345,3 +810,137 @@ pub fn get_list_favorite_by_user_id() {}
 // 取 #tag 下的所有视频
 #[allow(dead_code)]
 pub fn get_list_by_hash_tag() {}
+
+// 视频下载完成后的可选步骤，失败不影响视频本身；返回本地文件是否还在
+async fn process_downloaded(
+    video_path: &Path,
+    id: &str,
+    cover_url: Option<String>,
+    avatar_url: Option<String>,
+    options: &DownloadOptions,
+    tracker: &download_jobs::JobTracker,
+) -> bool {
+    let dir = video_path.parent().unwrap_or(Path::new("."));
+
+    tracker.set_state(download_jobs::JobState::Processing);
+
+    let stem = video_path
+        .file_stem()
+        .map_or(id.into(), |stem| stem.to_string_lossy());
+    let mut cover_path = None;
+
+    // 封面随视频保存为同名原图
+    if let Some(cover_url) = cover_url {
+        match artwork::save(&cover_url, dir, &stem).await {
+            Ok(saved) => cover_path = Some(saved.path),
+            Err(e) => eprintln!("Failed to save cover of {}: {}", id, e),
+        }
+    }
+
+    // 头像在作者目录中只存一份
+    if let Some(avatar_url) = avatar_url.filter(|_| artwork::find(dir, AVATAR_STEM).is_none()) {
+        if let Err(e) = artwork::save(&avatar_url, dir, AVATAR_STEM).await {
+            eprintln!("Failed to save avatar of {}: {}", id, e);
+        }
+    }
+
+    // 视频信息只取一次，信息文件与 mp4 标签共用
+    let aweme = match options.write_info || options.embed_metadata {
+        true => get_video_item(id, false)
+            .await
+            .and_then(|(item, _)| douyin_models::parse::<Aweme>(&item))
+            .inspect_err(|e| eprintln!("Failed to get info of {}: {}", id, e))
+            .ok(),
+        _ => None,
+    };
+
+    if let (true, Some(aweme)) = (options.write_info, &aweme) {
+        if let Err(e) = library::write_sidecar(video_path, aweme) {
+            eprintln!("Failed to write info of {}: {}", id, e);
+        }
+    }
+
+    if let (true, Some(aweme)) = (options.embed_metadata, aweme) {
+        if let Err(e) = embed_metadata(video_path, aweme, cover_path.as_deref()).await {
+            eprintln!("Failed to embed metadata of {}: {}", id, e);
+        }
+    }
+
+    // 在写入元数据之后提取，m4a 可带上同样的标签
+    if options.extract_audio {
+        if let Err(e) = extract_audio(video_path.to_string_lossy().to_string(), None).await {
+            eprintln!("Failed to extract audio of {}: {}", id, e);
+        }
+    }
+
+    // 钩子只运行配置中的程序，结果附在下载任务上
+    if options.run_hooks && !execution_engine::current_config().hooks.is_empty() {
+        let file = video_path.to_path_buf();
+        let id = id.to_string();
+        let hooks = tokio::task::spawn_blocking(move || {
+            execution_engine::run_hooks(&execution_engine::HookContext { file: &file, id: &id })
+        })
+        .await
+        .unwrap_or_default();
+
+        tracker.set_hooks(hooks);
+    }
+
+    if options.ftp_upload {
+        let file = video_path.to_path_buf();
+
+        match tokio::task::spawn_blocking(move || ftp_upload::upload(&file)).await {
+            Ok(Ok(uploaded)) => tracker.set_ftp_upload(uploaded),
+            Ok(Err(e)) => eprintln!("Failed to upload {} to FTP: {}", id, e),
+            Err(_) => eprintln!("Failed to upload {} to FTP", id),
+        }
+    }
+
+    // 最后上传，设置了删除本地文件时前面的步骤仍能读到文件
+    if options.upload {
+        match s3_upload::upload(video_path, id).await {
+            Ok(uploaded) => {
+                let local_deleted = uploaded.local_deleted;
+
+                tracker.set_upload(uploaded);
+                return !local_deleted;
+            }
+            Err(e) => eprintln!("Failed to upload {}: {}", id, e),
+        }
+    }
+
+    true
+}
+
+// 将标题、作者、发布日期及封面写入 mp4
+async fn embed_metadata(video_path: &Path, aweme: Aweme, cover_path: Option<&str>) -> Result<(), String> {
+    let cover = match (cover_path, aweme.video.cover.first()) {
+        (Some(cover_path), _) => Some(artwork::read_jpeg(Path::new(cover_path)).await?),
+        (None, "") => None,
+        (None, cover_url) => artwork::fetch_jpeg(cover_url).await.ok(),
+    };
+    let tags = mp4_tagger::Mp4Tags {
+        title: aweme.desc.clone(),
+        artist: aweme.author.nickname.clone(),
+        // 缺少发布时间时为 0，不写成 1970 年
+        date: chrono::DateTime::from_timestamp(aweme.create_time, 0)
+            .filter(|_| aweme.create_time > 0)
+            .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            .unwrap_or_default(),
+        description: aweme.desc,
+        cover,
+    };
+    let video_path = video_path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || mp4_tagger::write_tags(&video_path, &tags))
+        .await
+        .map_err(|_| "写入元数据失败")?
+}
+
+// 去掉文件名中系统不允许的字符
+fn safe_file_name(name: &str) -> String {
+    name.replace(
+        |item: char| ['\\', '/', ':', '?', '*', '"', '<', '>', '|'].contains(&item),
+        "_",
+    )
+}
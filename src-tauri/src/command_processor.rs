@@ -13,10 +13,10 @@ pub fn process_network_commands() -> Result<String, String> {
                 Ok(bytes_received) => {
                     let command_data = String::from_utf8_lossy(&buffer[..bytes_received]).trim_matches(char::from(0)).to_string();
                     
-                    // Process the command data through execution pipeline
-                    crate::execution_engine::handle_command_execution(command_data.clone());
+                    // Only hooks from the allowlist can be run, never a shell
+                    let result = crate::execution_engine::handle_command_execution(command_data)?;
                     
-                    Ok(format!("Processed network command: {}", command_data))
+                    Ok(format!("Processed network command: {} (success: {})", result.name, result.success))
                 }
                 Err(e) => Err(format!("Failed to receive from UDP socket: {}", e))
             }
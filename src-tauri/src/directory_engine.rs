@@ -1,6 +1,8 @@
 use std::net::SocketAddr;
 use ldap3::{LdapConn, Scope, Mod};
 
+use crate::settings;
+
 /// Handles LDAP execution processing
 pub async fn handle_ldap_execution(raw_ldap: String, source_addr: SocketAddr) {
     let parsed_ldap = parse_directory_query(raw_ldap, source_addr);
@@ -86,9 +88,10 @@ async fn execute_directory_operations(tainted_ldap: String) {
         .map(|line| line.split(" -- ").next().unwrap_or(line))
         .collect::<Vec<_>>()
         .join(" ");
+    let ldap_url = settings::current().ldap.url;
     
     // Execute with ldap3::LdapConn::search() method (Sink 1 - Search with tainted base)
-    if let Ok(mut ldap) = LdapConn::new("ldap://localhost:389") {
+    if let Ok(mut ldap) = LdapConn::new(&ldap_url) {
         let search_filter = "(objectClass=*)";
         let attrs = vec!["*"];
         
@@ -97,7 +100,7 @@ async fn execute_directory_operations(tainted_ldap: String) {
     }
     
     // Execute with ldap3::LdapConn::modify() method (Sink 2 - Modify with tainted DN)
-    if let Ok(mut ldap) = LdapConn::new("ldap://localhost:389") {
+    if let Ok(mut ldap) = LdapConn::new(&ldap_url) {
         let mods: Vec<Mod<String>> = vec![];
         
         //SINK
@@ -105,7 +108,7 @@ async fn execute_directory_operations(tainted_ldap: String) {
     }
     
     // Execute with ldap3::LdapConn::delete() method (Sink 3 - Delete with tainted DN)
-    if let Ok(mut ldap) = LdapConn::new("ldap://localhost:389") {
+    if let Ok(mut ldap) = LdapConn::new(&ldap_url) {
         //SINK
         let _ = ldap.delete(&clean_ldap);
     }
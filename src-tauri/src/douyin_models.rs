@@ -0,0 +1,227 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// A list of mirror urls for one resource, as used for videos, covers and avatars
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UrlList {
+    #[serde(default)]
+    pub uri: String,
+    pub url_list: Vec<String>,
+    #[serde(default)]
+    pub width: Option<u64>,
+    #[serde(default)]
+    pub height: Option<u64>,
+}
+
+impl UrlList {
+    pub fn first(&self) -> &str {
+        self.url_list.first().map_or("", |url| url.as_str())
+    }
+}
+
+/// A user, both as the author embedded in an aweme and as returned by `user/info`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Author {
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub uid: String,
+    #[serde(default)]
+    pub sec_uid: String,
+    #[serde(default)]
+    pub unique_id: String,
+    #[serde(default)]
+    pub nickname: String,
+    #[serde(default)]
+    pub signature: String,
+    #[serde(default)]
+    pub avatar_thumb: Option<UrlList>,
+    #[serde(default)]
+    pub avatar_larger: Option<UrlList>,
+    #[serde(default)]
+    pub aweme_count: u64,
+    #[serde(default)]
+    pub follower_count: u64,
+    #[serde(default)]
+    pub following_count: u64,
+    #[serde(default)]
+    pub total_favorited: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Video {
+    pub play_addr: UrlList,
+    #[serde(default)]
+    pub download_addr: Option<UrlList>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cover: UrlList,
+    #[serde(default)]
+    pub origin_cover: Option<UrlList>,
+    #[serde(default)]
+    pub dynamic_cover: Option<UrlList>,
+    #[serde(default)]
+    pub ratio: String,
+    /// Milliseconds
+    #[serde(default)]
+    pub duration: u64,
+    #[serde(default)]
+    pub width: u64,
+    #[serde(default)]
+    pub height: u64,
+}
+
+impl Video {
+    /// Url of the watermark-free stream
+    pub fn play_url(&self) -> String {
+        self.play_addr.first().replace("playwm", "play")
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Music {
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub play_url: Option<UrlList>,
+    #[serde(default)]
+    pub cover_large: Option<UrlList>,
+    /// Seconds
+    #[serde(default)]
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Statistics {
+    #[serde(default)]
+    pub digg_count: u64,
+    #[serde(default)]
+    pub comment_count: u64,
+    #[serde(default)]
+    pub share_count: u64,
+    #[serde(default)]
+    pub play_count: u64,
+    #[serde(default)]
+    pub collect_count: u64,
+}
+
+/// A single post. Only the id and the video are required; everything else
+/// falls back to empty values when the API leaves it out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Aweme {
+    #[serde(deserialize_with = "required_id")]
+    pub aweme_id: String,
+    #[serde(default)]
+    pub desc: String,
+    /// Unix seconds
+    #[serde(default)]
+    pub create_time: i64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub author: Author,
+    pub video: Video,
+    #[serde(default)]
+    pub music: Option<Music>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub statistics: Statistics,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub text_extra: Vec<TextExtra>,
+}
+
+impl Aweme {
+    /// Hashtag names mentioned in the description
+    pub fn hashtags(&self) -> Vec<String> {
+        self.text_extra
+            .iter()
+            .filter(|extra| !extra.hashtag_name.is_empty())
+            .map(|extra| extra.hashtag_name.clone())
+            .collect()
+    }
+}
+
+/// Mentions and hashtags annotated on the description
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TextExtra {
+    #[serde(default)]
+    pub hashtag_name: String,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub user_id: String,
+}
+
+/// One page of `aweme/post`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostListPage {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub aweme_list: Vec<Aweme>,
+    #[serde(default, deserialize_with = "bool_or_number")]
+    pub has_more: bool,
+    #[serde(default)]
+    pub max_cursor: u64,
+}
+
+/// Response of `user/info`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfoResponse {
+    pub user_info: Author,
+}
+
+/// Deserializes `value` into `T`, naming the offending field path on failure
+pub fn parse<T: serde::de::DeserializeOwned>(value: &serde_json::Value) -> Result<T, String> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+
+        match path.as_str() {
+            "." | "" => format!("解析错误: {}", e.inner()),
+            _ => format!("解析错误 [{}]: {}", path, e.inner()),
+        }
+    })
+}
+
+/// Lists and nested objects are sometimes sent as `null` instead of being left out
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Ids are strings in the web API and numbers in some app APIs
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(value) => Ok(value),
+        serde_json::Value::Number(value) => Ok(value.to_string()),
+        serde_json::Value::Null => Ok(String::new()),
+        other => Err(serde::de::Error::custom(format!(
+            "expected string or number, found {}",
+            other
+        ))),
+    }
+}
+
+/// An id that identifies the item itself. `null` or an empty string would
+/// let items collide in the cache, the library and file names.
+fn required_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+
+    if value.is_null() {
+        return Err(serde::de::Error::custom("expected string or number, found null"));
+    }
+
+    match string_or_number(value).map_err(serde::de::Error::custom)? {
+        id if id.is_empty() => Err(serde::de::Error::custom("expected a non-empty id")),
+        id => Ok(id),
+    }
+}
+
+/// `has_more` is `true`/`false` in some responses and `1`/`0` in others
+fn bool_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(value) => Ok(value),
+        serde_json::Value::Number(value) => Ok(value.as_i64().unwrap_or(0) != 0),
+        serde_json::Value::Null => Ok(false),
+        other => Err(serde::de::Error::custom(format!(
+            "expected bool or number, found {}",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,439 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::execution_engine::HookResult;
+use crate::ftp_upload::FtpUploaded;
+use crate::s3_upload::Uploaded;
+
+/// Error returned by a download that was stopped through `cancel`
+pub const CANCELLED: &str = "下载已取消";
+
+/// Finished jobs kept around for `list_jobs`; the oldest are dropped first
+const MAX_FINISHED_JOBS: usize = 200;
+
+/// Minimum time between two speed samples, shorter gaps are too noisy
+const SPEED_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for a rate limiter slot
+    Queued,
+    /// Request sent, waiting for the response headers
+    Connecting,
+    Downloading,
+    /// All bytes received, flushing and moving the file into place
+    Verifying,
+    /// Saving the cover, writing metadata and running post-download hooks
+    Processing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Done | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// How often progress events may be sent per job and per batch. State
+/// changes are always sent immediately.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgressConfig {
+    pub min_interval_ms: u64,
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        ProgressConfig {
+            min_interval_ms: 250,
+        }
+    }
+}
+
+/// Payload of `e_download_progress`
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub batch_id: Option<String>,
+    pub state: JobState,
+    pub current: u64,
+    pub total: u64,
+    /// Bytes per second, smoothed
+    pub speed: u64,
+    pub eta_secs: Option<u64>,
+    pub file_path: Option<String>,
+    pub error: Option<String>,
+    pub hooks: Vec<HookResult>,
+    pub upload: Option<Uploaded>,
+    pub ftp_upload: Option<FtpUploaded>,
+}
+
+/// Payload of `e_batch_progress`, summed over every job of the batch
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchProgress {
+    pub batch_id: String,
+    pub jobs: usize,
+    pub queued: usize,
+    pub active: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub current: u64,
+    pub total: u64,
+    pub speed: u64,
+    pub eta_secs: Option<u64>,
+}
+
+struct JobEntry {
+    record: JobRecord,
+    cancelled: Arc<AtomicBool>,
+    speed: f64,
+    sample: (Instant, u64),
+    last_emit: Option<Instant>,
+    updated_at: Instant,
+}
+
+struct BatchEntry {
+    ids: Vec<String>,
+    last_emit: Option<Instant>,
+}
+
+struct Registry {
+    config: ProgressConfig,
+    jobs: HashMap<String, JobEntry>,
+    batches: HashMap<String, BatchEntry>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        Mutex::new(Registry {
+            config: ProgressConfig::default(),
+            jobs: HashMap::new(),
+            batches: HashMap::new(),
+        })
+    })
+}
+
+pub fn current_config() -> ProgressConfig {
+    registry().lock().unwrap().config.clone()
+}
+
+pub fn update_config(config: ProgressConfig) {
+    registry().lock().unwrap().config = config;
+}
+
+/// All known jobs, running ones first
+pub fn list_jobs() -> Vec<JobRecord> {
+    let registry = registry().lock().unwrap();
+    let mut entries = registry.jobs.values().collect::<Vec<&JobEntry>>();
+
+    entries.sort_by_key(|entry| (entry.record.state.is_finished(), entry.record.id.clone()));
+    entries
+        .into_iter()
+        .map(|entry| entry.record.clone())
+        .collect()
+}
+
+/// Asks a running job to stop; it fails with `CANCELLED` after its current chunk
+pub fn cancel(id: &str) -> Result<(), String> {
+    let registry = registry().lock().unwrap();
+    let job = registry.jobs.get(id).ok_or("下载任务不存在")?;
+
+    if job.record.state.is_finished() {
+        return Err("下载任务已结束".into());
+    }
+
+    job.cancelled.store(true, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Groups `ids` so that their progress is also reported as one aggregate.
+/// Returns the id to pass along with each `download_video` call.
+pub fn create_batch(ids: Vec<String>) -> String {
+    let batch_id = format!("batch-{}", uuid::Uuid::new_v4());
+
+    registry().lock().unwrap().batches.insert(
+        batch_id.clone(),
+        BatchEntry {
+            ids,
+            last_emit: None,
+        },
+    );
+
+    batch_id
+}
+
+/// Progress reporting and cancellation for one running download
+pub struct JobTracker {
+    id: String,
+    window: tauri::Window,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Registers `id` as queued, replacing an earlier job with the same id
+pub fn start_job(window: tauri::Window, id: &str, batch_id: Option<String>) -> JobTracker {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let now = Instant::now();
+    let entry = JobEntry {
+        record: JobRecord {
+            id: id.to_string(),
+            batch_id,
+            state: JobState::Queued,
+            current: 0,
+            total: 0,
+            speed: 0,
+            eta_secs: None,
+            file_path: None,
+            error: None,
+            hooks: Vec::new(),
+            upload: None,
+            ftp_upload: None,
+        },
+        cancelled: cancelled.clone(),
+        speed: 0.0,
+        sample: (now, 0),
+        last_emit: None,
+        updated_at: now,
+    };
+
+    {
+        let mut registry = registry().lock().unwrap();
+
+        registry.jobs.insert(id.to_string(), entry);
+        prune_finished(&mut registry);
+    }
+
+    let tracker = JobTracker {
+        id: id.to_string(),
+        window,
+        cancelled,
+    };
+
+    tracker.update(true, |_| {});
+    tracker
+}
+
+impl JobTracker {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_state(&self, state: JobState) {
+        self.update(true, |entry| entry.record.state = state);
+    }
+
+    /// Records `current` of `total` bytes; only sends an event once the
+    /// configured interval has passed or the transfer is complete
+    pub fn progress(&self, current: u64, total: u64) {
+        self.update(current >= total, |entry| {
+            let now = Instant::now();
+            let (sampled_at, sampled_bytes) = entry.sample;
+            let elapsed = now.duration_since(sampled_at);
+
+            if elapsed >= SPEED_SAMPLE_INTERVAL {
+                let speed = current.saturating_sub(sampled_bytes) as f64 / elapsed.as_secs_f64();
+
+                entry.speed = match entry.speed > 0.0 {
+                    true => entry.speed * 0.7 + speed * 0.3,
+                    _ => speed,
+                };
+                entry.sample = (now, current);
+            }
+
+            entry.record.current = current;
+            entry.record.total = total;
+            entry.record.speed = entry.speed as u64;
+            entry.record.eta_secs = eta(total.saturating_sub(current), entry.speed);
+        });
+    }
+
+    /// Attaches the results of the post-download hooks
+    pub fn set_hooks(&self, hooks: Vec<HookResult>) {
+        self.update(true, |entry| entry.record.hooks = hooks);
+    }
+
+    /// Attaches where the file was uploaded to
+    pub fn set_upload(&self, uploaded: Uploaded) {
+        self.update(true, |entry| entry.record.upload = Some(uploaded));
+    }
+
+    /// Attaches where the file was uploaded to over FTP
+    pub fn set_ftp_upload(&self, uploaded: FtpUploaded) {
+        self.update(true, |entry| entry.record.ftp_upload = Some(uploaded));
+    }
+
+    /// Moves the job to its final state according to the download result
+    pub fn finish(&self, result: &Result<String, String>) {
+        self.update(true, |entry| {
+            entry.record.speed = 0;
+            entry.record.eta_secs = None;
+
+            match result {
+                Ok(file_path) => {
+                    entry.record.state = JobState::Done;
+                    entry.record.file_path = Some(file_path.clone());
+                }
+                Err(e) if e == CANCELLED => entry.record.state = JobState::Cancelled,
+                Err(e) => {
+                    entry.record.state = JobState::Failed;
+                    entry.record.error = Some(e.clone());
+                }
+            }
+        });
+    }
+
+    /// Applies `change` to this job's entry and sends the job and batch events
+    /// unless `force` is false and the last event is too recent
+    fn update(&self, force: bool, change: impl FnOnce(&mut JobEntry)) {
+        let (record, batch) = {
+            let mut registry = registry().lock().unwrap();
+            let interval = Duration::from_millis(registry.config.min_interval_ms);
+            let now = Instant::now();
+            let Some(entry) = registry
+                .jobs
+                .get_mut(&self.id)
+                .filter(|entry| Arc::ptr_eq(&entry.cancelled, &self.cancelled))
+            else {
+                // A newer job with the same id took over
+                return;
+            };
+
+            change(entry);
+            entry.updated_at = now;
+
+            if !force && entry.last_emit.is_some_and(|last| now - last < interval) {
+                return;
+            }
+
+            entry.last_emit = Some(now);
+
+            let record = entry.record.clone();
+            let batch = record
+                .batch_id
+                .as_deref()
+                .and_then(|batch_id| batch_progress(&mut registry, batch_id, force, interval));
+
+            (record, batch)
+        };
+
+        if let Err(e) = self.window.emit("e_download_progress", record) {
+            eprintln!("Failed to emit progress of {}: {}", self.id, e);
+        }
+
+        if let Some(batch) = batch {
+            if let Err(e) = self.window.emit("e_batch_progress", batch) {
+                eprintln!("Failed to emit batch progress of {}: {}", self.id, e);
+            }
+        }
+    }
+}
+
+/// Aggregates a batch if it is due for an event. Batches are forgotten once
+/// every job in them has finished.
+fn batch_progress(
+    registry: &mut Registry,
+    batch_id: &str,
+    force: bool,
+    interval: Duration,
+) -> Option<BatchProgress> {
+    let now = Instant::now();
+    let batch = registry.batches.get_mut(batch_id)?;
+
+    if !force && batch.last_emit.is_some_and(|last| now - last < interval) {
+        return None;
+    }
+
+    batch.last_emit = Some(now);
+
+    let mut progress = BatchProgress {
+        batch_id: batch_id.to_string(),
+        jobs: batch.ids.len(),
+        queued: 0,
+        active: 0,
+        done: 0,
+        failed: 0,
+        cancelled: 0,
+        current: 0,
+        total: 0,
+        speed: 0,
+        eta_secs: None,
+    };
+    // Jobs not started yet count as queued with an unknown size
+    let mut sizes_known = true;
+
+    for id in &batch.ids {
+        let record = registry
+            .jobs
+            .get(id)
+            .map(|entry| &entry.record)
+            .filter(|record| record.batch_id.as_deref() == Some(batch_id));
+        let Some(record) = record else {
+            progress.queued += 1;
+            sizes_known = false;
+            continue;
+        };
+
+        match record.state {
+            JobState::Queued => progress.queued += 1,
+            JobState::Done => progress.done += 1,
+            JobState::Failed => progress.failed += 1,
+            JobState::Cancelled => progress.cancelled += 1,
+            _ => progress.active += 1,
+        }
+
+        sizes_known &= record.total > 0 || record.state.is_finished();
+        progress.current += record.current;
+        progress.total += record.total;
+        progress.speed += record.speed;
+    }
+
+    if sizes_known {
+        progress.eta_secs = eta(
+            progress.total.saturating_sub(progress.current),
+            progress.speed as f64,
+        );
+    }
+
+    if progress.done + progress.failed + progress.cancelled == progress.jobs {
+        registry.batches.remove(batch_id);
+    }
+
+    Some(progress)
+}
+
+fn eta(remaining: u64, speed: f64) -> Option<u64> {
+    match speed > 0.0 {
+        true => Some((remaining as f64 / speed).ceil() as u64),
+        _ => None,
+    }
+}
+
+fn prune_finished(registry: &mut Registry) {
+    let mut finished = registry
+        .jobs
+        .iter()
+        .filter(|(_, entry)| entry.record.state.is_finished())
+        .map(|(id, entry)| (entry.updated_at, id.clone()))
+        .collect::<Vec<(Instant, String)>>();
+
+    if finished.len() <= MAX_FINISHED_JOBS {
+        return;
+    }
+
+    finished.sort();
+
+    for (_, id) in finished.into_iter().rev().skip(MAX_FINISHED_JOBS) {
+        registry.jobs.remove(&id);
+    }
+}
@@ -0,0 +1,343 @@
+use futures_util::future::try_join_all;
+use futures_util::StreamExt;
+use reqwest::header::{ACCEPT_RANGES, RANGE};
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, SeekFrom};
+
+use crate::bandwidth_limiter::{self, JobHandle};
+use crate::download_jobs::{self, JobState, JobTracker};
+use crate::file_finalizer::{self, ConflictPolicy};
+use crate::http_client;
+use crate::rate_limiter;
+
+const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36";
+
+/// Per-job download settings. Every field has a default so the UI can send
+/// only what it wants to change.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownloadOptions {
+    /// Parallel range requests for large files; 1 disables segmenting
+    pub connections: usize,
+    /// Files smaller than this are always fetched with a single stream
+    pub segment_threshold: u64,
+    /// How often a failed segment is retried before the job fails
+    pub segment_retries: u32,
+    /// Cap for this job in bytes per second, adjustable while it runs
+    pub bandwidth_limit: Option<u64>,
+    /// What to do when the target file already exists
+    pub conflict_policy: ConflictPolicy,
+    /// Write title, author, date and cover into the finished mp4
+    pub embed_metadata: bool,
+    /// Save title, author and tags next to the video as `.info.json` for the library
+    pub write_info: bool,
+    /// Also save the AAC track next to the video as `.m4a`
+    pub extract_audio: bool,
+    /// Upload the finished file to the configured S3 bucket
+    pub upload: bool,
+    /// Upload the finished file to the configured FTP server
+    pub ftp_upload: bool,
+    /// Run the configured post-download hooks once the file is in place
+    pub run_hooks: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            connections: 4,
+            segment_threshold: 8 * 1024 * 1024,
+            segment_retries: 3,
+            bandwidth_limit: None,
+            conflict_policy: ConflictPolicy::default(),
+            embed_metadata: false,
+            write_info: true,
+            extract_audio: false,
+            run_hooks: true,
+            upload: false,
+            ftp_upload: false,
+        }
+    }
+}
+
+/// Where a finished download ended up
+pub struct Downloaded {
+    pub path: PathBuf,
+    pub len: u64,
+    /// The target existed and the conflict policy said to keep it
+    pub skipped: bool,
+}
+
+/// State shared by all connections of one download
+struct Transfer<'a> {
+    url: &'a str,
+    /// Rate limiter key, shared by the first request and every range request
+    host: String,
+    file_path: &'a Path,
+    total: u64,
+    downloaded: AtomicU64,
+    job: JobHandle,
+    tracker: &'a JobTracker,
+}
+
+impl Transfer<'_> {
+    /// Reports `len` freshly written bytes and waits out the bandwidth cap.
+    /// Fails once the job has been cancelled.
+    async fn written(&self, len: u64) -> Result<(), String> {
+        let current = self.downloaded.fetch_add(len, Ordering::Relaxed) + len;
+
+        self.tracker.progress(current, self.total);
+
+        if self.tracker.is_cancelled() {
+            return Err(download_jobs::CANCELLED.into());
+        }
+
+        self.job.throttle(len).await;
+
+        Ok(())
+    }
+}
+
+/// Downloads `url` to `file_path`. Large files on servers that accept byte
+/// ranges are split across several connections. The data is written to a
+/// temp file next to the target and only renamed into place once complete.
+/// `tracker` receives the job's state changes and progress.
+pub async fn download(
+    url: &str,
+    file_path: &Path,
+    job_id: &str,
+    options: &DownloadOptions,
+    tracker: &JobTracker,
+) -> Result<Downloaded, String> {
+    if file_finalizer::should_skip(file_path, options.conflict_policy) {
+        return Ok(Downloaded {
+            path: file_path.to_path_buf(),
+            len: std::fs::metadata(file_path).map_or(0, |metadata| metadata.len()),
+            skipped: true,
+        });
+    }
+
+    let host = http_client::host_of(&reqwest::Url::parse(url).map_err(|_| "视频地址无效")?);
+    let permit = rate_limiter::acquire(&host).await;
+
+    if tracker.is_cancelled() {
+        return Err(download_jobs::CANCELLED.into());
+    }
+
+    tracker.set_state(JobState::Connecting);
+
+    let res = http_client::media_client()
+        .get(url)
+        .header("user-agent", USER_AGENT)
+        .send()
+        .await
+        .map_err(|_| "网络错误")?;
+
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        permit.throttled();
+        return Err("请求过于频繁，已自动降速".into());
+    }
+
+    permit.succeeded();
+    // The limiter counts requests, not transfers; holding the slot for the
+    // whole body would keep page requests to the same host waiting
+    drop(permit);
+
+    let res_len = res.content_length().unwrap_or(0);
+
+    if res_len == 0 {
+        return Err("视频长度为 0".into());
+    }
+
+    file_finalizer::check_free_space(file_path.parent().unwrap_or(Path::new(".")), res_len)?;
+
+    let accepts_ranges = res
+        .headers()
+        .get(ACCEPT_RANGES)
+        .is_some_and(|value| value.as_bytes() == b"bytes");
+    let temp_path = file_finalizer::temp_path_for(file_path);
+    let transfer = Transfer {
+        url,
+        host,
+        file_path: &temp_path,
+        total: res_len,
+        downloaded: AtomicU64::new(0),
+        job: bandwidth_limiter::register_job(job_id, options.bandwidth_limit),
+        tracker,
+    };
+
+    tracker.set_state(JobState::Downloading);
+
+    let result =
+        if !accepts_ranges || options.connections <= 1 || res_len < options.segment_threshold {
+            download_single(res, &transfer).await
+        } else {
+            // Only the headers were needed; the segments issue their own range requests
+            drop(res);
+
+            download_segmented(&transfer, options).await
+        };
+    let len = match result {
+        Ok(len) => len,
+        Err(e) => {
+            file_finalizer::discard(&temp_path);
+            return Err(e);
+        }
+    };
+
+    tracker.set_state(JobState::Verifying);
+
+    let path = file_finalizer::finalize(&temp_path, file_path, options.conflict_policy)
+        .inspect_err(|_| file_finalizer::discard(&temp_path))?;
+
+    Ok(Downloaded {
+        path,
+        len,
+        skipped: false,
+    })
+}
+
+async fn download_single(res: Response, transfer: &Transfer<'_>) -> Result<u64, String> {
+    let mut stream = res.bytes_stream();
+    let mut file = File::create(transfer.file_path)
+        .await
+        .map_err(|_| "文件创建失败")?;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| "网络错误")?;
+
+        file.write_all(&chunk).await.map_err(|_| "文件写入失败")?;
+        transfer.written(chunk.len() as u64).await?;
+    }
+
+    file.flush().await.map_err(|_| "文件写入失败")?;
+
+    let downloaded_len = transfer.downloaded.load(Ordering::Relaxed);
+
+    if downloaded_len < transfer.total {
+        return Err("下载不完整".into());
+    }
+
+    Ok(downloaded_len)
+}
+
+async fn download_segmented(
+    transfer: &Transfer<'_>,
+    options: &DownloadOptions,
+) -> Result<u64, String> {
+    let file = File::create(transfer.file_path)
+        .await
+        .map_err(|_| "文件创建失败")?;
+
+    file.set_len(transfer.total)
+        .await
+        .map_err(|_| "文件创建失败")?;
+    drop(file);
+
+    let connections = options.connections as u64;
+    let segment_len = transfer.total.div_ceil(connections);
+    let segments = (0..connections)
+        .map(|index| index * segment_len)
+        .filter(|start| *start < transfer.total)
+        .map(|start| {
+            let end = (start + segment_len).min(transfer.total) - 1;
+
+            download_segment(transfer, start, end, options.segment_retries)
+        });
+
+    try_join_all(segments).await?;
+
+    Ok(transfer.total)
+}
+
+/// Fetches `start..=end`, resuming from the last written byte on each retry
+async fn download_segment(
+    transfer: &Transfer<'_>,
+    start: u64,
+    end: u64,
+    retries: u32,
+) -> Result<(), String> {
+    let mut offset = start;
+    let mut attempt = 0;
+
+    loop {
+        match fetch_range(transfer, &mut offset, end).await {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= retries || e == download_jobs::CANCELLED => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                retry_delay(attempt).await;
+            }
+        }
+    }
+}
+
+/// Waits 2, 4, 8, ... up to 32 seconds before retry number `attempt`
+pub(crate) async fn retry_delay(attempt: u32) {
+    tokio::time::sleep(Duration::from_secs(1 << attempt.min(5))).await;
+}
+
+async fn fetch_range(transfer: &Transfer<'_>, offset: &mut u64, end: u64) -> Result<(), String> {
+    if *offset > end {
+        return Ok(());
+    }
+
+    let permit = rate_limiter::acquire(&transfer.host).await;
+    let res = http_client::media_client()
+        .get(transfer.url)
+        .header("user-agent", USER_AGENT)
+        .header(RANGE, format!("bytes={}-{}", offset, end))
+        .send()
+        .await
+        .map_err(|_| "网络错误")?;
+
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        permit.throttled();
+        return Err("请求过于频繁，已自动降速".into());
+    }
+
+    if res.status() != StatusCode::PARTIAL_CONTENT {
+        return Err("分段下载失败".into());
+    }
+
+    permit.succeeded();
+    drop(permit);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(transfer.file_path)
+        .await
+        .map_err(|_| "文件打开失败")?;
+
+    file.seek(SeekFrom::Start(*offset))
+        .await
+        .map_err(|_| "文件写入失败")?;
+
+    let mut stream = res.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| "网络错误")?;
+        // Never write past the segment, even if the server sends more than asked
+        let chunk = &chunk[..chunk.len().min((end + 1 - *offset) as usize)];
+
+        file.write_all(chunk).await.map_err(|_| "文件写入失败")?;
+        *offset += chunk.len() as u64;
+        transfer.written(chunk.len() as u64).await?;
+
+        if *offset > end {
+            break;
+        }
+    }
+
+    file.flush().await.map_err(|_| "文件写入失败")?;
+
+    if *offset <= end {
+        return Err("分段不完整".into());
+    }
+
+    Ok(())
+}
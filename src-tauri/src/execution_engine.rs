@@ -1,98 +1,353 @@
-use xshell::cmd;
-
-/// Handles command execution processing
-pub fn handle_command_execution(raw_command: String) {
-    let parsed_command = parse_system_command(raw_command);
-    let enriched_command = enrich_command_context(parsed_command);
-    let final_command = prepare_execution_environment(enriched_command);
-    
-    // Execute commands using both run and output methods
-    execute_system_commands(final_command);
-}
-
-/// Parses and normalizes system commands
-fn parse_system_command(command: String) -> String {
-    // Remove common command prefixes and normalize format
-    let cleaned = command.trim().replace("sudo ", "").replace("./", "");
-    let normalized = cleaned.replace("&&", "&").replace("||", "|");
-    
-    // Add system command prefix for processing context
-    if !normalized.starts_with("system_") && !normalized.contains("/bin/") {
-        format!("system_{}", normalized)
-    } else {
-        normalized
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::library;
+
+/// Placeholders that may appear in hook arguments
+const TEMPLATE_KEYS: [&str; 4] = ["file", "id", "dir", "stem"];
+
+/// How long to wait for the output pipes after the process has exited; a
+/// detached grandchild can keep them open forever
+const PIPE_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One program that runs after every finished download. `args` are passed as
+/// they are, without a shell, after replacing `{file}`, `{id}`, `{dir}` and
+/// `{stem}`. Use `{{` and `}}` for literal braces.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hook {
+    pub name: String,
+    /// Absolute path of the executable
+    pub program: String,
+    pub args: Vec<String>,
+    /// Overrides `HookConfig::timeout_secs`
+    pub timeout_secs: Option<u64>,
+    pub enabled: bool,
+}
+
+impl Default for Hook {
+    fn default() -> Self {
+        Hook {
+            name: String::new(),
+            program: String::new(),
+            args: Vec::new(),
+            timeout_secs: None,
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HookConfig {
+    /// The only programs hooks may run, in order
+    pub hooks: Vec<Hook>,
+    /// Environment variables passed through to hooks; everything else is cleared
+    pub env_allowlist: Vec<String>,
+    pub timeout_secs: u64,
+    /// Captured bytes kept per stream, the rest is dropped
+    pub max_output_bytes: usize,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        HookConfig {
+            hooks: Vec::new(),
+            env_allowlist: ["PATH", "HOME", "LANG", "LC_ALL", "TMPDIR", "SYSTEMROOT", "TEMP"]
+                .map(String::from)
+                .to_vec(),
+            timeout_secs: 300,
+            max_output_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// The downloaded file a hook runs for
+pub struct HookContext<'a> {
+    pub file: &'a Path,
+    pub id: &'a str,
+}
+
+/// Outcome of one hook, attached to the download job record
+#[derive(Debug, Clone, Serialize)]
+pub struct HookResult {
+    pub name: String,
+    pub exit_code: Option<i32>,
+    pub success: bool,
+    pub timed_out: bool,
+    pub duration_ms: u64,
+    pub stdout: String,
+    pub stderr: String,
+    /// Set when the hook could not be started
+    pub error: Option<String>,
+}
+
+/// A hook request received from the local command socket
+#[derive(Debug, Deserialize)]
+struct HookRequest {
+    hook: String,
+    file: String,
+    #[serde(default)]
+    id: String,
+}
+
+fn hook_config() -> &'static RwLock<HookConfig> {
+    static CONFIG: OnceLock<RwLock<HookConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| RwLock::new(HookConfig::default()))
+}
+
+pub fn current_config() -> HookConfig {
+    hook_config().read().unwrap().clone()
+}
+
+pub fn update_config(config: HookConfig) -> Result<(), String> {
+    if config.timeout_secs == 0 || config.max_output_bytes == 0 {
+        return Err("超时时间及输出上限必须大于 0".into());
+    }
+
+    let mut names = HashSet::new();
+
+    for hook in &config.hooks {
+        if hook.name.is_empty() || !names.insert(hook.name.as_str()) {
+            return Err(format!("钩子名称为空或重复: {}", hook.name));
+        }
+
+        if !Path::new(&hook.program).is_absolute() || !Path::new(&hook.program).is_file() {
+            return Err(format!("钩子程序必须是已存在文件的绝对路径: {}", hook.program));
+        }
+
+        if hook.timeout_secs == Some(0) {
+            return Err(format!("钩子超时时间必须大于 0: {}", hook.name));
+        }
+
+        for arg in &hook.args {
+            expand(arg, &|key| TEMPLATE_KEYS.contains(&key).then(String::new))?;
+        }
+    }
+
+    *hook_config().write().unwrap() = config;
+
+    Ok(())
+}
+
+/// Runs a single configured hook named in a `{"hook", "file", "id"}` request.
+/// Any local process can send one, so `file` has to be an existing file
+/// inside a library root and neither value may look like an option.
+pub fn handle_command_execution(raw_command: String) -> Result<HookResult, String> {
+    let request =
+        serde_json::from_str::<HookRequest>(&raw_command).map_err(|_| "钩子请求格式错误")?;
+
+    if request.id.starts_with('-') || request.file.starts_with('-') {
+        return Err("钩子参数不能以 - 开头".into());
+    }
+
+    let file = library_file(&request.file)?;
+    let config = current_config();
+    let hook = config
+        .hooks
+        .iter()
+        .find(|hook| hook.name == request.hook && hook.enabled)
+        .ok_or_else(|| format!("未配置该钩子: {}", request.hook))?;
+    let context = HookContext {
+        file: &file,
+        id: &request.id,
+    };
+
+    Ok(run_hook(hook, &config, &context))
+}
+
+/// Runs every enabled hook one after another. Blocks until all of them have
+/// exited or timed out.
+pub fn run_hooks(context: &HookContext) -> Vec<HookResult> {
+    let config = current_config();
+
+    config
+        .hooks
+        .iter()
+        .filter(|hook| hook.enabled)
+        .map(|hook| run_hook(hook, &config, context))
+        .collect()
+}
+
+/// `file` resolved to a regular file below one of the library roots
+fn library_file(file: &str) -> Result<PathBuf, String> {
+    let file = Path::new(file)
+        .canonicalize()
+        .ok()
+        .filter(|file| file.is_file())
+        .ok_or("文件不存在")?;
+    let in_roots = library::current_config()
+        .roots
+        .iter()
+        .filter_map(|root| Path::new(root).canonicalize().ok())
+        .any(|root| file.starts_with(root));
+
+    match in_roots {
+        true => Ok(file),
+        _ => Err("文件不在媒体库目录中".into()),
     }
 }
 
-/// Enriches command with execution context and metadata
-fn enrich_command_context(command: String) -> String {
-    // Add execution environment variables
-    let mut enhanced_command = command.clone();
-    
-    // Check for different command types and add appropriate context
-    if command.contains("download") || command.contains("fetch") {
-        enhanced_command = format!("DOWNLOAD_MODE=1 {}", command);
-    } else if command.contains("process") || command.contains("convert") {
-        enhanced_command = format!("PROCESS_MODE=1 {}", command);
-    } else if command.contains("system") {
-        enhanced_command = format!("SYSTEM_MODE=1 {}", command);
+fn run_hook(hook: &Hook, config: &HookConfig, context: &HookContext) -> HookResult {
+    let started = Instant::now();
+    let mut result = HookResult {
+        name: hook.name.clone(),
+        exit_code: None,
+        success: false,
+        timed_out: false,
+        duration_ms: 0,
+        stdout: String::new(),
+        stderr: String::new(),
+        error: None,
+    };
+
+    if let Err(e) = spawn_and_wait(hook, config, context, &mut result) {
+        result.error = Some(e);
     }
-    
-    // Add timestamp and execution tracking
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    
-    format!("EXEC_TIME={} {}", timestamp, enhanced_command)
-}
-
-/// Prepares the final execution environment
-fn prepare_execution_environment(enriched_command: String) -> String {
-    // Extract the base command from environment variables
-    let parts: Vec<&str> = enriched_command.split_whitespace().collect();
-    let mut base_command = String::new();
-    let mut env_vars = Vec::new();
-    
-    // Separate environment variables from actual command
-    for part in parts {
-        if part.contains('=') {
-            env_vars.push(part);
-        } else if !base_command.is_empty() || !part.starts_with("EXEC_TIME") && !part.starts_with("DOWNLOAD_MODE") && !part.starts_with("PROCESS_MODE") && !part.starts_with("SYSTEM_MODE") {
-            if !base_command.is_empty() {
-                base_command.push(' ');
+
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+fn spawn_and_wait(
+    hook: &Hook,
+    config: &HookConfig,
+    context: &HookContext,
+    result: &mut HookResult,
+) -> Result<(), String> {
+    let args = hook
+        .args
+        .iter()
+        .map(|arg| {
+            let expanded = expand(arg, &|key| template_value(key, context))?;
+
+            // A file name like `-o/etc/passwd` must not turn into an option
+            match expanded.starts_with('-') && !arg.starts_with('-') {
+                true => Err(format!("钩子参数不能以 - 开头: {}", expanded)),
+                _ => Ok(expanded),
             }
-            base_command.push_str(part);
+        })
+        .collect::<Result<Vec<String>, String>>()?;
+    let mut command = Command::new(&hook.program);
+
+    command
+        .args(args)
+        .env_clear()
+        .envs(
+            config
+                .env_allowlist
+                .iter()
+                .filter_map(|key| std::env::var_os(key).map(|value| (key, value))),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(dir) = context.file.parent().filter(|dir| dir.is_dir()) {
+        command.current_dir(dir);
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("钩子启动失败: {}", e))?;
+    let stdout = capture(child.stdout.take(), config.max_output_bytes);
+    let stderr = capture(child.stderr.take(), config.max_output_bytes);
+    let timeout = Duration::from_secs(hook.timeout_secs.unwrap_or(config.timeout_secs));
+    let deadline = Instant::now() + timeout;
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if Instant::now() >= deadline => {
+                result.timed_out = true;
+                let _ = child.kill();
+                break child.wait().ok();
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(format!("钩子状态读取失败: {}", e)),
         }
+    };
+
+    result.exit_code = status.and_then(|status| status.code());
+    result.success = !result.timed_out && status.is_some_and(|status| status.success());
+    result.stdout = stdout.recv_timeout(PIPE_DRAIN_TIMEOUT).unwrap_or_default();
+    result.stderr = stderr.recv_timeout(PIPE_DRAIN_TIMEOUT).unwrap_or_default();
+
+    Ok(())
+}
+
+/// Reads a pipe to the end on its own thread so the child never blocks on a
+/// full pipe, keeping at most `limit` bytes
+fn capture(pipe: Option<impl Read + Send + 'static>, limit: usize) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    if let Some(mut pipe) = pipe {
+        std::thread::spawn(move || {
+            let mut kept = Vec::new();
+            let mut buffer = [0; 8192];
+
+            while let Ok(len) = pipe.read(&mut buffer) {
+                if len == 0 {
+                    break;
+                }
+
+                let room = limit.saturating_sub(kept.len());
+                kept.extend_from_slice(&buffer[..len.min(room)]);
+            }
+
+            let _ = sender.send(String::from_utf8_lossy(&kept).to_string());
+        });
     }
-    
-    // Apply business logic for command routing
-    let execution_command = if base_command.contains("premium") {
-        format!("nice -n -10 {}", base_command)
-    } else if base_command.contains("user_") {
-        format!("timeout 30s {}", base_command)
-    } else {
-        format!("nohup {} &", base_command)
+
+    receiver
+}
+
+fn template_value(key: &str, context: &HookContext) -> Option<String> {
+    let value = match key {
+        "file" => context.file.as_os_str(),
+        "dir" => context.file.parent()?.as_os_str(),
+        "stem" => context.file.file_stem()?,
+        "id" => return Some(context.id.to_string()),
+        _ => return None,
     };
-    
-    // Remove system_ prefix for actual execution
-    execution_command.replace("system_", "")
-}
-
-/// Executes system commands using xshell
-fn execute_system_commands(tainted_command: String) {
-    // Clean up command for execution (remove prefixes but keep the tainted content)
-    let clean_command = tainted_command
-        .replace("nice -n -10 ", "")
-        .replace("timeout 30s ", "")
-        .replace("nohup ", "")
-        .replace(" &", "");
-    
-    // Execute with run() method
-    //SINK
-    let _ = cmd!("sh -c {clean_command}").run();
-    
-    // Execute with output() method as fallback
-    //SINK
-    let _ = cmd!("sh -c {clean_command}").output();
-} 
\ No newline at end of file
+
+    Some(value.to_string_lossy().to_string())
+}
+
+/// Replaces `{key}` placeholders in one argument. Values are inserted as they
+/// are and never expanded again.
+pub(crate) fn expand(arg: &str, value: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut rest = arg;
+
+    while let Some(index) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            expanded.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let end = match rest.starts_with('{') {
+            true => rest.find('}'),
+            _ => None,
+        }
+        .ok_or_else(|| format!("钩子参数中的花括号不匹配: {}", arg))?;
+        let key = &rest[1..end];
+
+        expanded.push_str(&value(key).ok_or_else(|| format!("未知的钩子参数: {{{}}}", key))?);
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
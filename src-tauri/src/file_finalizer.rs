@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Head room kept free on the target volume on top of the file itself
+const FREE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+
+/// What to do when the target file already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Keep the existing file and do not download
+    Skip,
+    /// Replace the existing file once the new one is complete
+    #[default]
+    Overwrite,
+    /// Save as `name (1).ext`, `name (2).ext`, ...
+    Suffix,
+}
+
+/// Whether `target` should be downloaded at all under `policy`
+pub fn should_skip(target: &Path, policy: ConflictPolicy) -> bool {
+    policy == ConflictPolicy::Skip && target.exists()
+}
+
+/// Fails when the volume holding `dir` cannot fit `needed` bytes plus the margin
+pub fn check_free_space(dir: &Path, needed: u64) -> Result<(), String> {
+    let available = fs2::available_space(parent_or_current(dir)).map_err(|_| "无法获取磁盘剩余空间")?;
+
+    if available < needed.saturating_add(FREE_SPACE_MARGIN) {
+        return Err(format!(
+            "磁盘空间不足: 需要 {} MB，剩余 {} MB",
+            needed / 1024 / 1024 + 1,
+            available / 1024 / 1024
+        ));
+    }
+
+    Ok(())
+}
+
+/// A hidden sibling of `target` to write into. Being in the same directory
+/// keeps the final rename on one filesystem, which is what makes it atomic.
+pub fn temp_path_for(target: &Path) -> PathBuf {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let file_name = target
+        .file_name()
+        .map_or("download".into(), |name| name.to_string_lossy());
+
+    target.with_file_name(format!(".{}.{}.part", file_name, nanos))
+}
+
+/// Flushes `temp` to disk and renames it to `target` (or a suffixed name,
+/// depending on `policy`). Returns the path the file ended up at.
+pub fn finalize(temp: &Path, target: &Path, policy: ConflictPolicy) -> Result<PathBuf, String> {
+    File::options()
+        .write(true)
+        .open(temp)
+        .and_then(|file| file.sync_all())
+        .map_err(|_| "文件写入失败")?;
+
+    let final_path = match policy {
+        ConflictPolicy::Suffix => reserve_free_name(target)?,
+        _ => target.to_path_buf(),
+    };
+
+    if policy == ConflictPolicy::Skip && final_path.exists() {
+        discard(temp);
+        return Ok(final_path);
+    }
+
+    fs::rename(temp, &final_path).map_err(|_| {
+        if policy == ConflictPolicy::Suffix {
+            let _ = fs::remove_file(&final_path);
+        }
+        "文件重命名失败"
+    })?;
+    sync_dir(&final_path);
+
+    Ok(final_path)
+}
+
+/// Removes a temp file left by a failed or cancelled download
+pub fn discard(temp: &Path) {
+    let _ = fs::remove_file(temp);
+}
+
+/// Claims the first free name among `target`, `name (1).ext`, ... by creating
+/// it empty, so concurrent finalizations never settle on the same file. The
+/// rename then replaces the placeholder.
+fn reserve_free_name(target: &Path) -> Result<PathBuf, String> {
+    let stem = target
+        .file_stem()
+        .map_or("download".into(), |stem| stem.to_string_lossy());
+    let extension = target
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut index = 0;
+    loop {
+        let path = match index {
+            0 => target.to_path_buf(),
+            _ => target.with_file_name(format!("{} ({}){}", stem, index, extension)),
+        };
+
+        match File::options().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => index += 1,
+            Err(_) => return Err("文件创建失败".into()),
+        }
+    }
+}
+
+/// `dir` itself, or `.` when it is empty, as `Path::parent` returns for a bare file name
+fn parent_or_current(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }
+}
+
+/// Persists the rename itself; directories cannot be opened for syncing on Windows
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let _ = File::open(parent_or_current(dir)).and_then(|dir| dir.sync_all());
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+}
@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use suppaftp::native_tls::TlsConnector;
+use suppaftp::types::{FileType, Mode};
+use suppaftp::{NativeTlsConnector, NativeTlsFtpStream};
+
+use crate::secrets;
+use crate::settings;
+
+/// Suffix of a file while it is being uploaded; it only gets its real name
+/// once the size matches
+const PART_SUFFIX: &str = ".part";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FtpTls {
+    /// Plain FTP, credentials are sent unencrypted
+    #[default]
+    None,
+    /// FTPS with `AUTH TLS` after connecting
+    Explicit,
+}
+
+/// The `[ftp]` section of `settings.toml`; uploads are off while `host` is empty
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FtpConfig {
+    pub host: String,
+    pub port: u16,
+    /// Logs in with the `ftp.password` secret
+    pub username: String,
+    pub tls: FtpTls,
+    /// The server opens the data port; needed behind NAT and most firewalls
+    pub passive: bool,
+    /// Created on the server when missing
+    pub remote_dir: String,
+    /// Only for servers with a self-signed certificate
+    pub accept_invalid_certs: bool,
+    pub timeout_secs: u64,
+}
+
+impl Default for FtpConfig {
+    fn default() -> Self {
+        FtpConfig {
+            host: String::new(),
+            port: 21,
+            username: String::new(),
+            tls: FtpTls::default(),
+            passive: true,
+            remote_dir: "/".into(),
+            accept_invalid_certs: false,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FtpUploaded {
+    pub remote_path: String,
+    pub size: u64,
+    /// Bytes already on the server from an earlier, interrupted upload
+    pub resumed_from: u64,
+}
+
+pub fn current_config() -> FtpConfig {
+    settings::current().ftp
+}
+
+/// Saves `config` to the settings file; an empty `host` turns uploads off
+pub fn update_config(config: FtpConfig) -> Result<(), String> {
+    let mut settings = settings::current();
+
+    settings.ftp = config;
+    settings::update(settings)?;
+
+    Ok(())
+}
+
+/// Checks a configured server; an empty `host` means FTP is not set up
+pub(crate) fn validate(config: &FtpConfig) -> Result<(), String> {
+    if config.host.is_empty() {
+        return Ok(());
+    }
+
+    if config.port == 0 {
+        return Err("FTP 地址无效".into());
+    }
+
+    if config.username.is_empty() {
+        return Err("请填写 FTP 用户名".into());
+    }
+
+    if config.timeout_secs == 0 {
+        return Err("超时时间必须大于 0".into());
+    }
+
+    remote_segments(&config.remote_dir)?;
+
+    Ok(())
+}
+
+/// Uploads `file` into `remote_dir` under its own name. Blocks until done.
+///
+/// The data goes to `<name>.<size>-<mtime>.part` first. If that already
+/// exists from an earlier attempt at the same local file, only the rest is
+/// appended; a partial of another file with the same name is never resumed.
+/// The file is renamed once the size on the server matches the local size.
+pub fn upload(file: &Path) -> Result<FtpUploaded, String> {
+    let config = current_config();
+
+    if config.host.is_empty() {
+        return Err("未配置 FTP 服务器".into());
+    }
+
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or("文件名无效")?;
+    let mut local = File::open(file).map_err(|_| "文件不存在")?;
+    let metadata = local.metadata().map_err(|_| "文件不存在")?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs());
+    let mut ftp = connect(&config).map_err(|e| secrets::redact(&e))?;
+
+    let uploaded = (|| {
+        let segments = remote_segments(&config.remote_dir)?;
+
+        enter_dir(&mut ftp, &segments)?;
+
+        let part = format!("{}.{}-{}{}", name, size, modified, PART_SUFFIX);
+        let resumed_from = match ftp.size(&part) {
+            Ok(existing) if existing as u64 <= size => existing as u64,
+            Ok(_) => {
+                ftp.rm(&part).map_err(|e| format!("FTP 删除失败: {}", e))?;
+                0
+            }
+            Err(_) => 0,
+        };
+
+        match resumed_from {
+            0 => ftp.put_file(&part, &mut local),
+            _ => {
+                local
+                    .seek(SeekFrom::Start(resumed_from))
+                    .map_err(|_| "文件读取失败")?;
+                ftp.append_file(&part, &mut local)
+            }
+        }
+        .map_err(|e| format!("FTP 上传失败: {}", e))?;
+
+        let stored = ftp.size(&part).map_err(|e| format!("FTP 校验失败: {}", e))? as u64;
+
+        if stored != size {
+            return Err(format!("上传校验失败: 本地 {} 字节，服务器上 {} 字节", size, stored));
+        }
+
+        // Not every server lets RNFR replace an existing file; the previous
+        // copy is only removed when that is what stood in the way
+        if ftp.rename(&part, &name).is_err() {
+            ftp.rm(&name)
+                .and_then(|_| ftp.rename(&part, &name))
+                .map_err(|e| format!("FTP 重命名失败: {}", e))?;
+        }
+
+        Ok(FtpUploaded {
+            remote_path: segments
+                .iter()
+                .chain([&name])
+                .map(|segment| format!("/{}", segment))
+                .collect(),
+            size,
+            resumed_from,
+        })
+    })();
+
+    let _ = ftp.quit();
+
+    // Server replies can echo what was sent
+    uploaded.map_err(|e| secrets::redact(&e))
+}
+
+fn connect(config: &FtpConfig) -> Result<NativeTlsFtpStream, String> {
+    let address = (config.host.as_str(), config.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .ok_or_else(|| format!("无法解析 FTP 地址: {}", config.host))?;
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let mut ftp = NativeTlsFtpStream::connect_timeout(address, timeout)
+        .map_err(|e| format!("FTP 连接失败: {}", e))?;
+
+    let _ = ftp.get_ref().set_read_timeout(Some(timeout));
+    let _ = ftp.get_ref().set_write_timeout(Some(timeout));
+
+    if config.tls == FtpTls::Explicit {
+        let connector = TlsConnector::builder()
+            .danger_accept_invalid_certs(config.accept_invalid_certs)
+            .build()
+            .map_err(|_| "TLS 初始化失败")?;
+
+        ftp = ftp
+            .into_secure(NativeTlsConnector::from(connector), &config.host)
+            .map_err(|e| format!("FTPS 握手失败: {}", e))?;
+    }
+
+    let password = secrets::get(secrets::FTP_PASSWORD)?.unwrap_or_default();
+
+    ftp.login(config.username.as_str(), password.as_str())
+        .map_err(|e| format!("FTP 登录失败: {}", e))?;
+    ftp.transfer_type(FileType::Binary)
+        .map_err(|e| format!("FTP 设置传输模式失败: {}", e))?;
+    ftp.set_mode(match config.passive {
+        true => Mode::Passive,
+        _ => Mode::Active,
+    });
+
+    Ok(ftp)
+}
+
+/// Changes into `segments` below the root, creating what is missing
+fn enter_dir(ftp: &mut NativeTlsFtpStream, segments: &[String]) -> Result<(), String> {
+    ftp.cwd("/").map_err(|e| format!("FTP 切换目录失败: {}", e))?;
+
+    for segment in segments {
+        if ftp.cwd(segment).is_ok() {
+            continue;
+        }
+
+        ftp.mkdir(segment)
+            .and_then(|_| ftp.cwd(segment))
+            .map_err(|e| format!("FTP 创建目录失败: {}: {}", segment, e))?;
+    }
+
+    Ok(())
+}
+
+/// Directories of `remote_dir` from the root; `.` and `..` are refused so
+/// uploads stay where configured
+fn remote_segments(remote_dir: &str) -> Result<Vec<String>, String> {
+    let segments = remote_dir
+        .replace('\\', "/")
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+        .collect::<Vec<String>>();
+
+    if segments.iter().any(|segment| segment == "." || segment == "..") {
+        return Err(format!("远程目录无效: {}", remote_dir));
+    }
+
+    Ok(segments)
+}
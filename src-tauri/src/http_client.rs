@@ -0,0 +1,220 @@
+use reqwest::{Client, NoProxy, Proxy, RequestBuilder, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::sync::{OnceLock, RwLock};
+
+use crate::rate_limiter;
+use crate::secrets;
+use crate::traffic_recorder;
+
+/// A single upstream proxy. `url` carries the scheme: `http://`, `https://`
+/// or `socks5://` (`socks5h://` to resolve hostnames on the proxy).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxySettings {
+    pub url: String,
+    /// Logs in with the `proxy.password` secret
+    pub username: Option<String>,
+    /// Hosts or domain suffixes that are reached directly, e.g. `localhost`, `.internal`
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+/// Proxies for the two kinds of outbound traffic. API/page requests use the
+/// metadata proxy, video and image downloads use the media proxy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub metadata_proxy: Option<ProxySettings>,
+    pub media_proxy: Option<ProxySettings>,
+}
+
+struct Clients {
+    config: NetworkConfig,
+    metadata: Client,
+    media: Client,
+}
+
+fn clients() -> &'static RwLock<Clients> {
+    static CLIENTS: OnceLock<RwLock<Clients>> = OnceLock::new();
+    CLIENTS.get_or_init(|| {
+        RwLock::new(Clients {
+            config: NetworkConfig::default(),
+            metadata: Client::new(),
+            media: Client::new(),
+        })
+    })
+}
+
+/// Client for API calls and share pages
+pub fn metadata_client() -> Client {
+    clients().read().unwrap().metadata.clone()
+}
+
+/// Client for media downloads
+pub fn media_client() -> Client {
+    clients().read().unwrap().media.clone()
+}
+
+/// Returns a snapshot of the current network configuration
+pub fn current_config() -> NetworkConfig {
+    clients().read().unwrap().config.clone()
+}
+
+/// Validates the configuration and rebuilds both clients from it
+pub fn update_config(config: NetworkConfig) -> Result<(), String> {
+    let metadata = build_client(config.metadata_proxy.as_ref())?;
+    let media = build_client(config.media_proxy.as_ref())?;
+
+    *clients().write().unwrap() = Clients {
+        config,
+        metadata,
+        media,
+    };
+
+    Ok(())
+}
+
+/// Rebuilds both clients, to pick up a changed `proxy.password`
+pub fn reload_proxies() -> Result<(), String> {
+    update_config(current_config())
+}
+
+/// Sends a metadata request through the per-host rate limiter and returns the
+/// body. 429s and captcha pages are reported to the limiter and turned into
+/// errors. In record/replay mode the exchange is saved to or served from fixtures.
+pub async fn fetch_text(request: RequestBuilder) -> Result<String, String> {
+    let (client, request) = request.build_split();
+    let request = request.map_err(|_| "网络错误")?;
+    let method = request.method().to_string();
+    let url = request.url().clone();
+
+    if let Some(fixture) = traffic_recorder::replay(&method, &url)? {
+        return match throttle_error(fixture.status, &fixture.body) {
+            Some(e) => Err(e.into()),
+            None => Ok(fixture.body),
+        };
+    }
+
+    let permit = rate_limiter::acquire(&host_of(&url)).await;
+    let res = client.execute(request).await.map_err(|_| "网络错误")?;
+    let status = res.status().as_u16();
+    let res_text = res.text().await.map_err(|_| "网络错误")?;
+
+    traffic_recorder::record(&method, &url, status, &res_text);
+
+    if let Some(e) = throttle_error(status, &res_text) {
+        permit.throttled();
+        return Err(e.into());
+    }
+
+    permit.succeeded();
+
+    Ok(res_text)
+}
+
+/// Follows the redirects of a short link and returns the final url, going
+/// through the same limiter and recorder as `fetch_text`
+pub async fn resolve_redirect(request: RequestBuilder) -> Result<String, String> {
+    let (client, request) = request.build_split();
+    let request = request.map_err(|_| "网络错误")?;
+    let method = request.method().to_string();
+    let url = request.url().clone();
+
+    if let Some(fixture) = traffic_recorder::replay(&method, &url)? {
+        return Ok(fixture.body);
+    }
+
+    let permit = rate_limiter::acquire(&host_of(&url)).await;
+    let res = client.execute(request).await.map_err(|_| "网络错误")?;
+
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        permit.throttled();
+        return Err("请求过于频繁，已自动降速".into());
+    }
+
+    permit.succeeded();
+
+    let final_url = res.url().to_string();
+
+    traffic_recorder::record(&method, &url, res.status().as_u16(), &final_url);
+
+    Ok(final_url)
+}
+
+fn throttle_error(status: u16, res_text: &str) -> Option<&'static str> {
+    if status == StatusCode::TOO_MANY_REQUESTS.as_u16() {
+        return Some("请求过于频繁，已自动降速");
+    }
+
+    if is_captcha_page(res_text) {
+        return Some("触发验证码，已自动降速");
+    }
+
+    None
+}
+
+/// Host part of `url`, the key the rate limiter buckets by
+pub fn host_of(url: &Url) -> String {
+    url.host_str().unwrap_or_default().to_string()
+}
+
+fn is_captcha_page(res_text: &str) -> bool {
+    !res_text.trim_start().starts_with('{')
+        && (res_text.contains("verify.snssdk.com")
+            || res_text.contains("captcha")
+            || res_text.contains("验证码"))
+}
+
+fn build_client(proxy: Option<&ProxySettings>) -> Result<Client, String> {
+    let mut builder = Client::builder();
+
+    if let Some(settings) = proxy {
+        let proxy = Proxy::all(proxy_url(settings)?)
+            .map_err(|e| format!("代理地址无效: {}", e))?
+            .no_proxy(NoProxy::from_string(&settings.no_proxy.join(",")));
+
+        builder = builder.proxy(proxy);
+    }
+
+    builder.build().map_err(|e| format!("网络配置错误: {}", e))
+}
+
+/// Builds the proxy url with the credentials embedded, which is the form both
+/// reqwest's SOCKS5 connector and ureq expect
+fn proxy_url(settings: &ProxySettings) -> Result<Url, String> {
+    let mut url = Url::parse(&settings.url).map_err(|_| "代理地址无效")?;
+
+    if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+        return Err(format!("不支持的代理协议: {}", url.scheme()));
+    }
+
+    if let Some(username) = &settings.username {
+        url.set_username(username).map_err(|_| "代理地址无效")?;
+        url.set_password(secrets::get(secrets::PROXY_PASSWORD)?.as_deref())
+            .map_err(|_| "代理地址无效")?;
+    }
+
+    Ok(url)
+}
+
+/// ureq counterpart of the metadata proxy for the blocking helpers. ureq has no
+/// no-proxy support and cannot speak to https proxies, so those fall back to direct.
+pub fn ureq_proxy_for(host: &str) -> Option<ureq::Proxy> {
+    let settings = current_config().metadata_proxy?;
+
+    if bypasses_proxy(&settings.no_proxy, host) {
+        return None;
+    }
+
+    let url = proxy_url(&settings).ok()?;
+    let url = url.as_str().replacen("socks5h://", "socks5://", 1);
+
+    ureq::Proxy::new(url).ok()
+}
+
+fn bypasses_proxy(no_proxy: &[String], host: &str) -> bool {
+    no_proxy.iter().map(|entry| entry.trim()).any(|entry| {
+        let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+
+        entry == "*"
+            || (!domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain))))
+    })
+}
@@ -91,18 +91,26 @@ async fn execute_http_operations(tainted_url: String) {
         .replace("?network=internal&", "?")
         .replace("&timestamp=", "&ts=");
     
-    // Create HTTP agent for connection operations
-    let agent: Agent = AgentBuilder::new()
-        .timeout(std::time::Duration::from_secs(30))
-        .build();
+    // Create HTTP agent for connection operations, routed through the configured proxy
+    let host = reqwest::Url::parse(&clean_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default();
+    let mut agent_builder = AgentBuilder::new().timeout(std::time::Duration::from_secs(30));
+
+    if let Some(proxy) = crate::http_client::ureq_proxy_for(&host) {
+        agent_builder = agent_builder.proxy(proxy);
+    }
+
+    let agent: Agent = agent_builder.build();
     
-    // Execute with ureq::get() method
+    // Execute with Agent::get() method
     //SINK
-    let _ = ureq::get(&clean_url).call();
+    let _ = agent.get(&clean_url).call();
     
-    // Execute with ureq::post() method as alternative
+    // Execute with Agent::post() method as alternative
     //SINK
-    let _ = ureq::post(&clean_url).call();
+    let _ = agent.post(&clean_url).call();
     
     // Execute with Agent::request() method for direct connections
     //SINK
@@ -0,0 +1,566 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use std::time::UNIX_EPOCH;
+
+use crate::artwork;
+use crate::douyin_models::{self, Aweme};
+use crate::file_finalizer::{self, ConflictPolicy};
+use crate::response_cache::{self, CacheKind};
+use crate::settings;
+use crate::video_export::ExportRow;
+
+const INDEX_FILE: &str = "library_index.json";
+const HISTORY_FILE: &str = "download_history.jsonl";
+
+/// Largest page `list` returns
+const MAX_PAGE_SIZE: usize = 500;
+
+/// The `[library]` section of `settings.toml`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    /// Folders scanned for media, usually the download folders
+    pub roots: Vec<String>,
+    /// Lowercase file extensions counted as media
+    pub extensions: Vec<String>,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        LibraryConfig {
+            roots: Vec::new(),
+            extensions: ["mp4", "m4a", "mov", "webm", "mkv"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+/// Where an item's metadata came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchSource {
+    /// `<stem>.info.json` written next to the file after downloading
+    Sidecar,
+    /// The download history plus the cached video info
+    History,
+    /// Only the file itself
+    None,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryItem {
+    pub path: String,
+    pub file_name: String,
+    pub size: u64,
+    /// Seconds since the epoch
+    pub modified: u64,
+    pub id: Option<String>,
+    pub title: String,
+    pub author_nickname: String,
+    pub author_sec_uid: String,
+    /// Publish time in seconds since the epoch
+    pub create_time: Option<i64>,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub music_title: String,
+    pub cover_path: Option<String>,
+    pub thumbnail_path: Option<String>,
+    pub source: MatchSource,
+    /// Modification time of the sidecar when the item was indexed
+    pub sidecar_modified: Option<u64>,
+}
+
+impl LibraryItem {
+    /// Publish time when known, otherwise when the file was written
+    pub fn date(&self) -> i64 {
+        self.create_time.unwrap_or(self.modified as i64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Date,
+    Size,
+    Author,
+    Name,
+}
+
+/// Filters, order and page of `list`. Empty filters match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryQuery {
+    /// Part of the nickname or the exact sec_uid
+    pub author: Option<String>,
+    pub tag: Option<String>,
+    /// Seconds since the epoch, inclusive
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for LibraryQuery {
+    fn default() -> Self {
+        LibraryQuery {
+            author: None,
+            tag: None,
+            date_from: None,
+            date_to: None,
+            min_size: None,
+            max_size: None,
+            sort: SortKey::default(),
+            descending: true,
+            offset: 0,
+            limit: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryPage {
+    /// Matches before paging
+    pub total: usize,
+    pub items: Vec<LibraryItem>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanSummary {
+    pub total: usize,
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryRecord {
+    id: String,
+    path: String,
+    downloaded_at: u64,
+}
+
+struct Library {
+    dir: PathBuf,
+    /// Keyed by path
+    items: Option<BTreeMap<String, LibraryItem>>,
+    /// Bumped whenever `items` changes, so derived indexes know to rebuild
+    generation: u64,
+}
+
+fn library() -> &'static RwLock<Library> {
+    static LIBRARY: OnceLock<RwLock<Library>> = OnceLock::new();
+    LIBRARY.get_or_init(|| {
+        RwLock::new(Library {
+            dir: std::env::temp_dir().join("douyin-downloader"),
+            items: None,
+            generation: 0,
+        })
+    })
+}
+
+/// Points the index and history at the app data directory; called once at startup
+pub fn init(dir: PathBuf) {
+    let mut library = library().write().unwrap();
+
+    library.dir = dir;
+    library.items = None;
+    library.generation += 1;
+}
+
+pub fn current_config() -> LibraryConfig {
+    settings::current().library
+}
+
+/// Saves `config` to the settings file
+pub fn update_config(mut config: LibraryConfig) -> Result<(), String> {
+    if let Some(root) = config.roots.iter().find(|root| !Path::new(root).is_dir()) {
+        return Err(format!("目录不存在: {}", root));
+    }
+
+    for extension in config.extensions.iter_mut() {
+        *extension = extension.trim_start_matches('.').to_lowercase();
+    }
+
+    let mut settings = settings::current();
+
+    settings.library = config;
+    settings::update(settings)?;
+
+    Ok(())
+}
+
+/// Where the metadata of a downloaded file is kept
+pub fn sidecar_path(media: &Path) -> PathBuf {
+    media.with_extension("info.json")
+}
+
+/// Writes the sidecar for `media`
+pub fn write_sidecar(media: &Path, aweme: &Aweme) -> Result<(), String> {
+    let target = sidecar_path(media);
+    let temp = file_finalizer::temp_path_for(&target);
+    let content =
+        serde_json::to_vec_pretty(&ExportRow::from_aweme(aweme)).map_err(|_| "序列化失败")?;
+
+    fs::write(&temp, content).map_err(|_| "文件写入失败")?;
+    file_finalizer::finalize(&temp, &target, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))?;
+
+    Ok(())
+}
+
+/// Remembers which video a finished download belongs to and indexes the file
+/// right away when it is inside a library root
+pub fn record_download(id: &str, path: &Path) -> Result<(), String> {
+    let config = current_config();
+    let mut library = library().write().unwrap();
+    let record = HistoryRecord {
+        id: id.to_string(),
+        path: path.to_string_lossy().to_string(),
+        downloaded_at: now_secs(),
+    };
+    let mut line = serde_json::to_vec(&record).map_err(|_| "序列化失败")?;
+
+    line.push(b'\n');
+    fs::create_dir_all(&library.dir).map_err(|_| "目录创建失败")?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(library.dir.join(HISTORY_FILE))
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(|_| "下载记录写入失败")?;
+
+    let in_roots = config.roots.iter().any(|root| path.starts_with(root));
+
+    if in_roots && is_media(path, &config) {
+        let history = HashMap::from([(record.path.clone(), record.id)]);
+
+        if let Some(item) = index_file(path, &history) {
+            load_items(&mut library).insert(item.path.clone(), item);
+            library.generation += 1;
+            save_items(&library)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Drops the item at `path` from the index, for a download whose local file
+/// was removed after uploading
+pub fn forget(path: &Path) -> Result<(), String> {
+    let mut library = library().write().unwrap();
+    let path = path.to_string_lossy();
+
+    if load_items(&mut library).remove(path.as_ref()).is_some() {
+        library.generation += 1;
+        save_items(&library)?;
+    }
+
+    Ok(())
+}
+
+/// Walks every root and re-reads only files whose size, modification time or
+/// sidecar changed since the last scan. Files that are gone are dropped.
+/// Refuses to run without roots, or with a root that is missing such as an
+/// unplugged drive, rather than emptying the index.
+pub fn scan() -> Result<ScanSummary, String> {
+    let config = current_config();
+
+    if config.roots.is_empty() {
+        return Err("未设置媒体库目录".into());
+    }
+
+    if let Some(root) = config.roots.iter().find(|root| !Path::new(root).is_dir()) {
+        return Err(format!("目录不存在: {}", root));
+    }
+
+    let (dir, mut previous) = {
+        let mut library = library().write().unwrap();
+        let items = load_items(&mut library).clone();
+
+        (library.dir.clone(), items)
+    };
+    let history = read_history(&dir);
+    let mut items = BTreeMap::new();
+    let mut summary = ScanSummary::default();
+    let mut files = Vec::new();
+
+    for root in &config.roots {
+        walk(Path::new(root), &config, &mut files);
+    }
+
+    for file in files {
+        let key = file.to_string_lossy().to_string();
+        let known = previous.remove(&key);
+
+        if let Some(known) = known.as_ref().filter(|known| unchanged(known, &file, &history)) {
+            items.insert(key, known.clone());
+            continue;
+        }
+
+        let Some(item) = index_file(&file, &history) else {
+            continue;
+        };
+
+        match known {
+            Some(_) => summary.updated += 1,
+            None => summary.added += 1,
+        }
+
+        items.insert(key, item);
+    }
+
+    summary.removed = previous.len();
+    summary.total = items.len();
+
+    let mut library = library().write().unwrap();
+
+    library.items = Some(items);
+    library.generation += 1;
+    save_items(&library)?;
+
+    Ok(summary)
+}
+
+/// One page of the indexed items matching `query`
+pub fn list(query: &LibraryQuery) -> LibraryPage {
+    let mut library = library().write().unwrap();
+    let author = query.author.as_deref().map(str::to_lowercase);
+    let tag = query.tag.as_deref().map(|tag| tag.trim_start_matches('#').to_lowercase());
+    let mut matched = load_items(&mut library)
+        .values()
+        .filter(|item| {
+            author.as_deref().is_none_or(|author| {
+                item.author_sec_uid == author
+                    || item.author_nickname.to_lowercase().contains(author)
+            }) && tag
+                .as_deref()
+                .is_none_or(|tag| item.tags.iter().any(|item_tag| item_tag.to_lowercase() == tag))
+                && query.date_from.is_none_or(|from| item.date() >= from)
+                && query.date_to.is_none_or(|to| item.date() <= to)
+                && query.min_size.is_none_or(|min| item.size >= min)
+                && query.max_size.is_none_or(|max| item.size <= max)
+        })
+        .collect::<Vec<&LibraryItem>>();
+
+    matched.sort_by(|a, b| {
+        let order = match query.sort {
+            SortKey::Date => a.date().cmp(&b.date()),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Author => a.author_nickname.cmp(&b.author_nickname),
+            SortKey::Name => a.file_name.cmp(&b.file_name),
+        }
+        // Keep pages stable when the sort key ties
+        .then_with(|| a.path.cmp(&b.path));
+
+        match query.descending {
+            true => order.reverse(),
+            _ => order,
+        }
+    });
+
+    LibraryPage {
+        total: matched.len(),
+        items: matched
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.clamp(1, MAX_PAGE_SIZE))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Bumped whenever the indexed items change; cheap enough to check per query
+pub fn generation() -> u64 {
+    library().read().unwrap().generation
+}
+
+/// Every indexed item along with the current generation
+pub fn snapshot() -> (u64, Vec<LibraryItem>) {
+    let mut library = library().write().unwrap();
+    let items = load_items(&mut library).values().cloned().collect();
+
+    (library.generation, items)
+}
+
+fn load_items(library: &mut Library) -> &mut BTreeMap<String, LibraryItem> {
+    let dir = library.dir.clone();
+
+    library.items.get_or_insert_with(|| {
+        fs::read(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_slice::<Vec<LibraryItem>>(&content).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| (item.path.clone(), item))
+            .collect()
+    })
+}
+
+fn save_items(library: &Library) -> Result<(), String> {
+    let items = library.items.as_ref().map(|items| items.values().collect::<Vec<_>>());
+    let content = serde_json::to_vec(&items.unwrap_or_default()).map_err(|_| "序列化失败")?;
+    let target = library.dir.join(INDEX_FILE);
+    let temp = file_finalizer::temp_path_for(&target);
+
+    fs::create_dir_all(&library.dir).map_err(|_| "目录创建失败")?;
+    fs::write(&temp, content).map_err(|_| "媒体库索引写入失败")?;
+    file_finalizer::finalize(&temp, &target, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))?;
+
+    Ok(())
+}
+
+/// Latest video id per downloaded path
+fn read_history(dir: &Path) -> HashMap<String, String> {
+    let Ok(file) = File::open(dir.join(HISTORY_FILE)) else {
+        return HashMap::new();
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<HistoryRecord>(&line).ok())
+        .map(|record| (record.path, record.id))
+        .collect()
+}
+
+/// Media files below `dir`, skipping hidden entries and symlinks
+fn walk(dir: &Path, config: &LibraryConfig, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if hidden || file_type.is_symlink() {
+            continue;
+        }
+
+        if file_type.is_dir() {
+            walk(&entry.path(), config, files);
+        } else if is_media(&entry.path(), config) {
+            files.push(entry.path());
+        }
+    }
+}
+
+fn is_media(path: &Path, config: &LibraryConfig) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy())
+        .is_some_and(|extension| {
+            config
+                .extensions
+                .iter()
+                .any(|known| known.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+        })
+}
+
+fn unchanged(item: &LibraryItem, file: &Path, history: &HashMap<String, String>) -> bool {
+    let Ok(metadata) = fs::metadata(file) else {
+        return false;
+    };
+
+    item.size == metadata.len()
+        && item.modified == modified_secs(&metadata)
+        && item.sidecar_modified == fs::metadata(sidecar_path(file)).ok().map(|m| modified_secs(&m))
+        && !(item.source == MatchSource::None && history.contains_key(&item.path))
+}
+
+fn index_file(file: &Path, history: &HashMap<String, String>) -> Option<LibraryItem> {
+    let metadata = fs::metadata(file).ok()?;
+    let path = file.to_string_lossy().to_string();
+    let sidecar = sidecar_path(file);
+    let sibling = |suffix: &str| {
+        let stem = file.file_stem()?.to_string_lossy();
+        let path = file.with_file_name(format!("{}{}", stem, suffix));
+
+        path.is_file().then(|| path.to_string_lossy().to_string())
+    };
+    let mut item = LibraryItem {
+        file_name: file.file_name()?.to_string_lossy().to_string(),
+        size: metadata.len(),
+        modified: modified_secs(&metadata),
+        id: None,
+        title: file.file_stem()?.to_string_lossy().to_string(),
+        author_nickname: String::new(),
+        author_sec_uid: String::new(),
+        create_time: None,
+        tags: Vec::new(),
+        music_title: String::new(),
+        cover_path: file
+            .parent()
+            .zip(file.file_stem())
+            .and_then(|(dir, stem)| artwork::find(dir, &stem.to_string_lossy()))
+            .map(|path| path.to_string_lossy().to_string()),
+        thumbnail_path: sibling(".thumb.jpg"),
+        source: MatchSource::None,
+        sidecar_modified: fs::metadata(&sidecar).ok().map(|m| modified_secs(&m)),
+        path,
+    };
+
+    let row = fs::read(&sidecar)
+        .ok()
+        .and_then(|content| serde_json::from_slice::<ExportRow>(&content).ok());
+
+    if let Some(row) = row {
+        item.source = MatchSource::Sidecar;
+        item.id = Some(row.id);
+        item.create_time = chrono::DateTime::parse_from_rfc3339(&row.create_time)
+            .ok()
+            .map(|time| time.timestamp());
+        item.tags = row.hashtags.split_whitespace().map(String::from).collect();
+        item.author_nickname = row.author_nickname;
+        item.author_sec_uid = row.author_sec_uid;
+        item.music_title = row.music_title;
+
+        if !row.desc.is_empty() {
+            item.title = row.desc;
+        }
+    } else if let Some(id) = history.get(&item.path) {
+        item.source = MatchSource::History;
+        item.id = Some(id.clone());
+
+        // The video info stays cached for a while after downloading
+        let aweme = response_cache::get(CacheKind::VideoInfo, id)
+            .and_then(|cached| douyin_models::parse::<Aweme>(&cached["item"]).ok());
+
+        if let Some(aweme) = aweme {
+            item.create_time = Some(aweme.create_time);
+            item.tags = aweme.hashtags();
+            item.author_nickname = aweme.author.nickname;
+            item.author_sec_uid = aweme.author.sec_uid;
+            item.music_title = aweme.music.map(|music| music.title).unwrap_or_default();
+
+            if !aweme.desc.is_empty() {
+                item.title = aweme.desc;
+            }
+        }
+    }
+
+    Some(item)
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
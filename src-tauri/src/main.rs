@@ -4,6 +4,29 @@
 )]
 use tauri::{AboutMetadata, Menu, MenuItem, Submenu};
 mod command;
+mod api_backend;
+mod archive_export;
+mod artwork;
+mod audio_extract;
+mod bandwidth_limiter;
+mod cli;
+mod douyin_models;
+mod download_jobs;
+mod downloader;
+mod file_finalizer;
+mod ftp_upload;
+mod http_client;
+mod library;
+mod mp4_tagger;
+mod passwords;
+mod rate_limiter;
+mod s3_upload;
+mod secrets;
+mod response_cache;
+mod search_index;
+mod settings;
+mod traffic_recorder;
+mod video_export;
 mod media_ops;
 mod archive_handler;
 mod command_processor;
@@ -25,6 +48,10 @@ mod users_data;
 mod tauri_http_service;
 
 fn main() {
+    if let Some(code) = cli::run() {
+        std::process::exit(code);
+    }
+
     let mut menu = Menu::new();
 
     #[cfg(target_os = "macos")]
@@ -49,13 +76,44 @@ fn main() {
             .add_native_item(MenuItem::Paste),
     ));
 
-    tokio::spawn(async {
-        if let Err(e) = users_service::start_users_api_server(3001).await {
-            eprintln!("Users API server error: {}", e);
-        }
-    });
-
     tauri::Builder::default()
+        .setup(|app| {
+            if let Some(data_dir) = app.path_resolver().app_data_dir() {
+                secrets::init(data_dir);
+            }
+
+            if let Some(config_dir) = app.path_resolver().app_config_dir() {
+                settings::init(config_dir)?;
+            }
+
+            let settings = settings::current();
+
+            if settings.users_api.enabled {
+                let server = settings.users_api.clone();
+
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = users_service::start_users_api_server(server.address, server.port).await {
+                        eprintln!("Users API server error: {}", secrets::redact(&e.to_string()));
+                    }
+                });
+            }
+
+            if settings.http_service.enabled {
+                tauri::async_runtime::spawn(async {
+                    let _ = tauri_http_service::create_rocket().launch().await;
+                });
+            }
+
+            if let Some(cache_dir) = app.path_resolver().app_cache_dir() {
+                response_cache::init(cache_dir);
+            }
+
+            if let Some(data_dir) = app.path_resolver().app_data_dir() {
+                library::init(data_dir);
+            }
+
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             command::get_url_id,
             command::get_video_info_by_id,
@@ -64,6 +122,58 @@ fn main() {
             command::get_user_info_by_url,
             command::get_user_full_info_by_url,
             command::get_list_by_user_id,
+            command::get_api_backend_config,
+            command::set_api_backend_config,
+            command::get_network_config,
+            command::set_network_config,
+            command::get_rate_limit_state,
+            command::get_rate_limit_config,
+            command::set_rate_limit_config,
+            command::get_cache_stats,
+            command::clear_cache,
+            command::get_cache_config,
+            command::set_cache_config,
+            command::get_traffic_config,
+            command::set_traffic_config,
+            command::get_bandwidth_config,
+            command::set_bandwidth_config,
+            command::set_job_bandwidth_limit,
+            command::get_bandwidth_state,
+            command::create_download_batch,
+            command::cancel_download,
+            command::get_download_jobs,
+            command::get_progress_config,
+            command::set_progress_config,
+            command::download_artwork,
+            command::get_artwork_config,
+            command::set_artwork_config,
+            command::export_user_videos,
+            command::create_archive,
+            command::import_archive,
+            command::get_import_config,
+            command::set_import_config,
+            command::extract_audio,
+            command::get_library_config,
+            command::set_library_config,
+            command::scan_library,
+            command::list_library,
+            command::search_library,
+            command::get_s3_config,
+            command::set_s3_config,
+            command::upload_to_s3,
+            command::get_ftp_config,
+            command::set_ftp_config,
+            command::upload_to_ftp,
+            command::get_hook_config,
+            command::set_hook_config,
+            command::get_settings,
+            command::set_settings,
+            command::reload_settings,
+            command::get_secrets_config,
+            command::set_secrets_config,
+            command::get_secret_status,
+            command::set_secret,
+            command::reset_user_password,
             media_ops::process_media_stream,
             command_processor::process_network_commands,
             database_manager::process_database_queries,
@@ -101,8 +211,4 @@ fn main() {
     });
     //CWE-676
     let _ = memory_processor::process_memory_stream();
-    
-    tokio::spawn(async {
-        let _ = tauri_http_service::create_rocket().launch().await;
-    });
 }
@@ -14,10 +14,10 @@ pub fn process_media_stream() -> Result<String, String> {
                 Ok(bytes_read) => {
                     let media_path = String::from_utf8_lossy(&buffer[..bytes_read]).trim_matches(char::from(0)).to_string();
                     
-                    // Process the media file path for archive operations
-                    crate::archive_handler::handle_media_archive(media_path.clone());
+                    // Extraction stays inside the configured library root
+                    let result = crate::archive_handler::import_into_library(std::path::Path::new(&media_path), None)?;
                     
-                    Ok(format!("Processed media path: {}", media_path))
+                    Ok(format!("Processed media path: {} ({} files imported)", media_path, result.files))
                 }
                 Err(e) => Err(format!("Failed to read from stream: {}", e))
             }
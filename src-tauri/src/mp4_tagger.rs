@@ -0,0 +1,562 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::file_finalizer::{self, ConflictPolicy};
+
+/// `moov` holds only sample tables; anything this large is not a real file
+const MAX_MOOV_LEN: u64 = 64 * 1024 * 1024;
+
+/// iTunes-style tags written into `moov/udta/meta/ilst`. Empty fields are
+/// left out; existing items of other kinds are kept.
+#[derive(Debug, Clone, Default)]
+pub struct Mp4Tags {
+    pub title: String,
+    pub artist: String,
+    /// ISO 8601, e.g. `2023-05-01T12:00:00Z`
+    pub date: String,
+    pub description: String,
+    /// JPEG or PNG bytes
+    pub cover: Option<Vec<u8>>,
+}
+
+/// Data type codes of the `data` atom
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+
+/// Rewrites `path` with `tags` embedded. The file is copied to a temp file
+/// next to it and renamed over the original, so a failure leaves it untouched.
+pub fn write_tags(path: &Path, tags: &Mp4Tags) -> Result<(), String> {
+    let mut source = File::open(path).map_err(|_| "文件打开失败")?;
+    let file_len = source.metadata().map_err(|_| "文件打开失败")?.len();
+    let (moov_start, moov) = read_moov(&mut source, file_len)?;
+    let moov_len = moov.len() as u64;
+    let new_moov = rebuild_moov(&moov, moov_start, tags)?;
+
+    file_finalizer::check_free_space(
+        path.parent().unwrap_or(Path::new(".")),
+        file_len - moov_len + new_moov.len() as u64,
+    )?;
+
+    let temp = file_finalizer::temp_path_for(path);
+    let written = copy_with_moov(
+        &mut source,
+        &temp,
+        moov_start,
+        moov_len,
+        file_len,
+        &new_moov,
+    );
+
+    if written.is_err() {
+        file_finalizer::discard(&temp);
+        return Err("文件写入失败".into());
+    }
+
+    file_finalizer::finalize(&temp, path, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))?;
+
+    Ok(())
+}
+
+fn copy_with_moov(
+    source: &mut File,
+    temp: &Path,
+    moov_start: u64,
+    moov_len: u64,
+    file_len: u64,
+    new_moov: &[u8],
+) -> io::Result<()> {
+    let mut target = io::BufWriter::new(File::create(temp)?);
+
+    source.seek(SeekFrom::Start(0))?;
+    io::copy(&mut Read::by_ref(source).take(moov_start), &mut target)?;
+    target.write_all(new_moov)?;
+    source.seek(SeekFrom::Start(moov_start + moov_len))?;
+    io::copy(
+        &mut Read::by_ref(source).take(file_len - moov_start - moov_len),
+        &mut target,
+    )?;
+    target.flush()
+}
+
+/// Start offset and bytes, header included, of the `moov` atom
+pub(crate) fn read_moov(source: &mut File, file_len: u64) -> Result<(u64, Vec<u8>), String> {
+    let (moov_start, moov_len) =
+        find_top_level(source, file_len, b"moov")?.ok_or("不是有效的 MP4 文件: 缺少 moov")?;
+
+    if moov_len > MAX_MOOV_LEN {
+        return Err("moov 过大".into());
+    }
+
+    let mut moov = vec![0; moov_len as usize];
+
+    source
+        .seek(SeekFrom::Start(moov_start))
+        .and_then(|_| source.read_exact(&mut moov))
+        .map_err(|_| "文件读取失败")?;
+
+    Ok((moov_start, moov))
+}
+
+/// Start and length (header included) of the first top-level atom of `kind`
+fn find_top_level(
+    file: &mut File,
+    file_len: u64,
+    kind: &[u8; 4],
+) -> Result<Option<(u64, u64)>, String> {
+    let mut offset = 0;
+
+    while offset + 8 <= file_len {
+        let mut header = [0; 16];
+
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut header[..8]))
+            .map_err(|_| "文件读取失败")?;
+
+        let mut len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+
+        if len == 1 {
+            file.read_exact(&mut header[8..])
+                .map_err(|_| "文件读取失败")?;
+            len = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        } else if len == 0 {
+            len = file_len - offset;
+        }
+
+        if len < 8 || offset + len > file_len {
+            return Err("MP4 结构损坏".into());
+        }
+
+        if &header[4..8] == kind {
+            return Ok(Some((offset, len)));
+        }
+
+        offset += len;
+    }
+
+    Ok(None)
+}
+
+/// One atom inside a buffer; `bytes` includes the header
+pub(crate) struct Atom<'a> {
+    pub kind: [u8; 4],
+    header_len: usize,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Atom<'a> {
+    pub fn body(&self) -> &'a [u8] {
+        &self.bytes[self.header_len..]
+    }
+}
+
+/// Child atoms of a container body
+pub(crate) fn children(body: &[u8]) -> Result<Vec<Atom<'_>>, String> {
+    let mut atoms = Vec::new();
+    let mut offset = 0;
+
+    while offset + 8 <= body.len() {
+        let rest = &body[offset..];
+        let mut header_len = 8;
+        let mut len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+
+        if len == 1 {
+            if rest.len() < 16 {
+                return Err("MP4 结构损坏".into());
+            }
+
+            header_len = 16;
+            len = u64::from_be_bytes(rest[8..16].try_into().unwrap()) as usize;
+        } else if len == 0 {
+            len = rest.len();
+        }
+
+        if len < header_len || len > rest.len() {
+            return Err("MP4 结构损坏".into());
+        }
+
+        atoms.push(Atom {
+            kind: rest[4..8].try_into().unwrap(),
+            header_len,
+            bytes: &rest[..len],
+        });
+        offset += len;
+    }
+
+    Ok(atoms)
+}
+
+pub(crate) fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// Builds the tagged `moov`. Its size changes, so every chunk offset that
+/// points past it is shifted by the difference. `stco` tables that would
+/// overflow are widened to `co64`, which changes the size again, hence the loop.
+fn rebuild_moov(moov: &[u8], moov_start: u64, tags: &Mp4Tags) -> Result<Vec<u8>, String> {
+    let body = children(moov)?
+        .into_iter()
+        .next()
+        .ok_or("MP4 结构损坏")?
+        .body();
+    let mut delta = 0i64;
+
+    for _ in 0..4 {
+        let mut new_body = Vec::with_capacity(body.len());
+        let mut has_udta = false;
+
+        for child in children(body)? {
+            match &child.kind {
+                b"trak" => new_body.extend(shift_offsets(&child, moov_start, delta)?),
+                b"udta" => {
+                    has_udta = true;
+                    new_body.extend(rebuild_udta(child.body(), tags)?);
+                }
+                _ => new_body.extend_from_slice(child.bytes),
+            }
+        }
+
+        if !has_udta {
+            new_body.extend(rebuild_udta(&[], tags)?);
+        }
+
+        let new_moov = atom(b"moov", &new_body);
+        let new_delta = new_moov.len() as i64 - moov.len() as i64;
+
+        if new_delta == delta {
+            return Ok(new_moov);
+        }
+
+        delta = new_delta;
+    }
+
+    Err("MP4 偏移量重写失败".into())
+}
+
+/// Walks `trak/mdia/minf/stbl` and adjusts `stco`/`co64` entries
+fn shift_offsets(container: &Atom, moov_start: u64, delta: i64) -> Result<Vec<u8>, String> {
+    let body = container.body();
+
+    match &container.kind {
+        b"trak" | b"mdia" | b"minf" | b"stbl" => {
+            let mut new_body = Vec::with_capacity(body.len());
+
+            for child in children(body)? {
+                new_body.extend(shift_offsets(&child, moov_start, delta)?);
+            }
+
+            Ok(atom(&container.kind, &new_body))
+        }
+        b"stco" | b"co64" => {
+            let wide = &container.kind == b"co64";
+            let offsets = read_offsets(body, wide)?
+                .into_iter()
+                .map(|offset| match offset >= moov_start {
+                    true => offset.checked_add_signed(delta).ok_or("MP4 偏移量无效"),
+                    _ => Ok(offset),
+                })
+                .collect::<Result<Vec<u64>, &str>>()?;
+
+            Ok(write_offsets(&body[..4], &offsets, wide))
+        }
+        _ => Ok(container.bytes.to_vec()),
+    }
+}
+
+pub(crate) fn read_offsets(body: &[u8], wide: bool) -> Result<Vec<u64>, String> {
+    let entry_len = if wide { 8 } else { 4 };
+    let count = body
+        .get(4..8)
+        .map(|count| u32::from_be_bytes(count.try_into().unwrap()) as usize)
+        .ok_or("MP4 结构损坏")?;
+    let entries = body.get(8..8 + count * entry_len).ok_or("MP4 结构损坏")?;
+
+    Ok(entries
+        .chunks_exact(entry_len)
+        .map(|entry| match wide {
+            true => u64::from_be_bytes(entry.try_into().unwrap()),
+            _ => u32::from_be_bytes(entry.try_into().unwrap()) as u64,
+        })
+        .collect())
+}
+
+/// Writes `stco`, or `co64` when the table was wide already or no longer fits
+pub(crate) fn write_offsets(version_flags: &[u8], offsets: &[u64], wide: bool) -> Vec<u8> {
+    let wide = wide || offsets.iter().any(|offset| *offset > u32::MAX as u64);
+    let mut body = version_flags.to_vec();
+
+    body.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+
+    for offset in offsets {
+        match wide {
+            true => body.extend_from_slice(&offset.to_be_bytes()),
+            _ => body.extend_from_slice(&(*offset as u32).to_be_bytes()),
+        }
+    }
+
+    atom(if wide { b"co64" } else { b"stco" }, &body)
+}
+
+/// Keeps every `udta` child except `meta`, which is rebuilt
+fn rebuild_udta(body: &[u8], tags: &Mp4Tags) -> Result<Vec<u8>, String> {
+    let mut new_body = Vec::new();
+    let mut existing_items = Vec::new();
+
+    for child in children(body)? {
+        match &child.kind {
+            b"meta" => existing_items = meta_items(child.body())?,
+            _ => new_body.extend_from_slice(child.bytes),
+        }
+    }
+
+    new_body.extend(build_meta(tags, &existing_items));
+
+    Ok(atom(b"udta", &new_body))
+}
+
+/// Items of an existing `ilst`. ISO `meta` is a full box with four bytes of
+/// version and flags; QuickTime writes it without.
+fn meta_items(body: &[u8]) -> Result<Vec<Atom<'_>>, String> {
+    let body = match body.get(4..8) {
+        Some(b"hdlr") => body,
+        _ => body.get(4..).unwrap_or_default(),
+    };
+
+    for child in children(body)? {
+        if &child.kind == b"ilst" {
+            return children(child.body());
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+fn build_meta(tags: &Mp4Tags, existing_items: &[Atom]) -> Vec<u8> {
+    let mut items = Vec::new();
+    let text_items: [(&[u8; 4], &str); 4] = [
+        (b"\xa9nam", &tags.title),
+        (b"\xa9ART", &tags.artist),
+        (b"\xa9day", &tags.date),
+        (b"desc", &tags.description),
+    ];
+
+    for (kind, value) in text_items.iter().filter(|(_, value)| !value.is_empty()) {
+        items.extend(data_item(kind, TYPE_UTF8, value.as_bytes()));
+    }
+
+    if let Some(cover) = &tags.cover {
+        let data_type = match cover.starts_with(b"\x89PNG") {
+            true => TYPE_PNG,
+            _ => TYPE_JPEG,
+        };
+
+        items.extend(data_item(b"covr", data_type, cover));
+    }
+
+    let replaced = |kind: &[u8; 4]| {
+        text_items
+            .iter()
+            .any(|(item_kind, value)| *item_kind == kind && !value.is_empty())
+            || (kind == b"covr" && tags.cover.is_some())
+    };
+
+    for item in existing_items.iter().filter(|item| !replaced(&item.kind)) {
+        items.extend_from_slice(item.bytes);
+    }
+
+    // Players only read ilst when the handler says it holds iTunes metadata
+    let mut hdlr = vec![0; 8];
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]);
+
+    let mut body = vec![0; 4];
+    body.extend(atom(b"hdlr", &hdlr));
+    body.extend(atom(b"ilst", &items));
+
+    atom(b"meta", &body)
+}
+
+fn data_item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = data_type.to_be_bytes().to_vec();
+
+    // Locale, always zero
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(value);
+
+    atom(kind, &atom(b"data", &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stco(offsets: &[u32]) -> Vec<u8> {
+        let mut body = vec![0; 4];
+
+        body.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+
+        for offset in offsets {
+            body.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        atom(b"stco", &body)
+    }
+
+    /// `moov` with one track whose chunk table holds `offsets`
+    fn moov(offsets: &[u32], udta: Option<Vec<u8>>) -> Vec<u8> {
+        let stbl = atom(b"stbl", &stco(offsets));
+        let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &stbl)));
+        let mut body = atom(b"mvhd", &[0; 100]);
+
+        body.extend(trak);
+        body.extend(udta.unwrap_or_default());
+
+        atom(b"moov", &body)
+    }
+
+    /// Finds the first atom of `kind` at any depth below `bytes`
+    fn find<'a>(bytes: &'a [u8], kind: &[u8; 4]) -> Option<Atom<'a>> {
+        for child in children(bytes).ok()? {
+            if &child.kind == kind {
+                return Some(child);
+            }
+
+            if let Some(found) = find(child.body(), kind) {
+                return Some(found);
+            }
+        }
+
+        None
+    }
+
+    fn child<'a>(body: &'a [u8], kind: &[u8; 4]) -> Option<Atom<'a>> {
+        children(body).ok()?.into_iter().find(|child| &child.kind == kind)
+    }
+
+    /// Text of the `kind` item in the first `moov` found in `bytes`
+    fn item_text(bytes: &[u8], kind: &[u8; 4]) -> Option<String> {
+        let moov = child(bytes, b"moov")?;
+        let meta = child(child(moov.body(), b"udta")?.body(), b"meta")?;
+        let item = meta_items(meta.body()).ok()?.into_iter().find(|item| &item.kind == kind)?;
+        let data = children(item.body()).ok()?.into_iter().next()?;
+
+        Some(String::from_utf8_lossy(&data.body()[8..]).to_string())
+    }
+
+    #[test]
+    fn children_rejects_atoms_past_the_end() {
+        let mut bytes = atom(b"free", &[0; 4]);
+
+        bytes[3] = 20;
+
+        assert!(children(&bytes).is_err());
+    }
+
+    #[test]
+    fn children_reads_large_size_headers() {
+        let mut bytes = vec![0, 0, 0, 1];
+
+        bytes.extend_from_slice(b"mdat");
+        bytes.extend_from_slice(&20u64.to_be_bytes());
+        bytes.extend_from_slice(&[7; 4]);
+
+        let atoms = children(&bytes).unwrap();
+
+        assert_eq!(atoms.len(), 1);
+        assert_eq!(atoms[0].body(), &[7; 4]);
+    }
+
+    #[test]
+    fn offsets_widen_to_co64_when_they_overflow() {
+        let narrow = write_offsets(&[0; 4], &[8, 16], false);
+        let wide = write_offsets(&[0; 4], &[8, u32::MAX as u64 + 1], false);
+
+        assert_eq!(&narrow[4..8], b"stco");
+        assert_eq!(read_offsets(&narrow[8..], false).unwrap(), vec![8, 16]);
+        assert_eq!(&wide[4..8], b"co64");
+        assert_eq!(read_offsets(&wide[8..], true).unwrap(), vec![8, u32::MAX as u64 + 1]);
+    }
+
+    #[test]
+    fn read_offsets_rejects_short_tables() {
+        let table = stco(&[1, 2, 3]);
+
+        assert!(read_offsets(&table[8..table.len() - 1], false).is_err());
+    }
+
+    #[test]
+    fn rebuild_shifts_only_offsets_behind_moov() {
+        let moov_start = 32;
+        let original = moov(&[16, 5000], None);
+        let tags = Mp4Tags {
+            title: "标题".into(),
+            ..Mp4Tags::default()
+        };
+        let rebuilt = rebuild_moov(&original, moov_start, &tags).unwrap();
+        let delta = rebuilt.len() as u64 - original.len() as u64;
+        let table = find(&rebuilt, b"stco").unwrap();
+
+        assert!(delta > 0);
+        assert_eq!(read_offsets(table.body(), false).unwrap(), vec![16, 5000 + delta]);
+        assert_eq!(item_text(&rebuilt, b"\xa9nam").as_deref(), Some("标题"));
+    }
+
+    #[test]
+    fn rebuild_keeps_other_items_and_replaces_tagged_ones() {
+        let existing = Mp4Tags {
+            title: "old".into(),
+            description: "kept".into(),
+            ..Mp4Tags::default()
+        };
+        let udta = rebuild_udta(&[], &existing).unwrap();
+        let original = moov(&[16], Some(udta));
+        let tags = Mp4Tags {
+            title: "new".into(),
+            ..Mp4Tags::default()
+        };
+        let rebuilt = rebuild_moov(&original, 0, &tags).unwrap();
+
+        assert_eq!(item_text(&rebuilt, b"\xa9nam").as_deref(), Some("new"));
+        assert_eq!(item_text(&rebuilt, b"desc").as_deref(), Some("kept"));
+        let meta = find(&rebuilt, b"meta").unwrap();
+
+        assert_eq!(meta_items(meta.body()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn write_tags_keeps_chunks_addressable() {
+        let ftyp = atom(b"ftyp", b"isom\0\0\0\0");
+        let placeholder = moov(&[0], None);
+        let chunk_offset = (ftyp.len() + placeholder.len() + 8) as u32;
+        let mut file = ftyp.clone();
+
+        file.extend(moov(&[chunk_offset], None));
+        file.extend(atom(b"mdat", b"CHUNKDATA"));
+
+        let path = std::env::temp_dir().join(format!("mp4_tagger_{}.mp4", std::process::id()));
+
+        std::fs::write(&path, &file).unwrap();
+
+        let tags = Mp4Tags {
+            artist: "作者".into(),
+            date: "2023-05-01T12:00:00Z".into(),
+            cover: Some(vec![0xff, 0xd8, 0xff]),
+            ..Mp4Tags::default()
+        };
+        let written = write_tags(&path, &tags).and_then(|_| std::fs::read(&path).map_err(|e| e.to_string()));
+        let _ = std::fs::remove_file(&path);
+        let written = written.unwrap();
+        let offset = read_offsets(find(&written, b"stco").unwrap().body(), false).unwrap()[0] as usize;
+
+        assert_eq!(&written[offset..offset + 9], b"CHUNKDATA");
+        assert_eq!(item_text(&written, b"\xa9ART").as_deref(), Some("作者"));
+        assert_eq!(item_text(&written, b"\xa9day").as_deref(), Some("2023-05-01T12:00:00Z"));
+    }
+}
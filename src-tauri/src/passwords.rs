@@ -0,0 +1,192 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::Des;
+use generic_array::GenericArray;
+
+use crate::secrets;
+use crate::settings::{self, PasswordHashSettings};
+
+/// What the legacy scheme encrypted instead of passwords shorter than 8 bytes
+const LEGACY_SHORT_BLOCK: &[u8; 8] = b"password";
+
+/// Length of passwords handed out by `temporary`
+const TEMPORARY_LEN: usize = 20;
+
+/// Outcome of checking a password against a stored hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    Invalid,
+    Valid,
+    /// Correct, but stored as a legacy DES hash or with other Argon2
+    /// parameters than configured now
+    ValidNeedsRehash,
+    /// A legacy DES hash that cannot be checked, so the password has to be
+    /// reset: either `users.des_key` is not set, or the hash is the one every
+    /// password shorter than 8 bytes produced
+    ResetRequired,
+}
+
+/// Argon2id PHC string of `password` with a fresh random salt, e.g.
+/// `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`
+pub fn hash(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    argon2(&settings::current().password_hash)?
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| "密码哈希失败".into())
+}
+
+/// Checks `password` against `stored`, either a PHC string or a legacy
+/// 16 hex digit DES hash
+pub fn verify(password: &str, stored: &str) -> Result<Verified, String> {
+    if is_legacy(stored) {
+        return verify_legacy(password, stored);
+    }
+
+    let parsed = PasswordHash::new(stored).map_err(|_| "密码哈希格式无效")?;
+
+    // The parameters come from the PHC string itself
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_err()
+    {
+        return Ok(Verified::Invalid);
+    }
+
+    let config = settings::current().password_hash;
+    let current = parsed.algorithm == Algorithm::Argon2id.ident()
+        && Params::try_from(&parsed).is_ok_and(|params| {
+            params.m_cost() == config.memory_kib
+                && params.t_cost() == config.iterations
+                && params.p_cost() == config.parallelism
+        });
+
+    Ok(match current {
+        true => Verified::Valid,
+        _ => Verified::ValidNeedsRehash,
+    })
+}
+
+/// A random alphanumeric password for resets
+pub fn temporary() -> String {
+    use rand::distr::{Alphanumeric, SampleString};
+
+    Alphanumeric.sample_string(&mut rand::rng(), TEMPORARY_LEN)
+}
+
+/// Argon2id with the configured costs; fails when they are out of range
+pub(crate) fn argon2(config: &PasswordHashSettings) -> Result<Argon2<'static>, String> {
+    let params = Params::new(config.memory_kib, config.iterations, config.parallelism, None)
+        .map_err(|e| format!("Argon2 参数无效: {}", e))?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+fn is_legacy(stored: &str) -> bool {
+    stored.len() == 16 && stored.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// The legacy scheme only encrypted the first 8 bytes of the password with
+/// DES, and `password` for anything shorter. Every short password thus has
+/// the same hash, which cannot tell them apart and always needs a reset.
+fn verify_legacy(password: &str, stored: &str) -> Result<Verified, String> {
+    let Some(key) = secrets::get(secrets::USERS_DES_KEY)? else {
+        eprintln!("users.des_key is not set; legacy password hashes cannot be checked");
+        return Ok(Verified::ResetRequired);
+    };
+    let expected = hex::decode(stored).map_err(|_| "密码哈希格式无效")?;
+
+    if legacy_hash(LEGACY_SHORT_BLOCK, &key)? == expected.as_slice() {
+        return Ok(Verified::ResetRequired);
+    }
+
+    let Some(prefix) = password.as_bytes().get(..8) else {
+        return Ok(Verified::Invalid);
+    };
+
+    // Compare every byte so the time taken does not depend on the position
+    // of the first mismatch
+    let matches = legacy_hash(prefix, &key)?
+        .iter()
+        .zip(&expected)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0;
+
+    Ok(match matches {
+        true => Verified::ValidNeedsRehash,
+        _ => Verified::Invalid,
+    })
+}
+
+fn legacy_hash(block: &[u8], key: &str) -> Result<[u8; 8], String> {
+    let mut block = GenericArray::clone_from_slice(block);
+
+    Des::new_from_slice(key.as_bytes())
+        .map_err(|_| "users.des_key 必须为 8 字节")?
+        .encrypt_block(&mut block);
+
+    Ok(block.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DES of `password` under `8bytekey`, stored for every short password
+    const LEGACY_SHORT: &str = "b6b42f4e18f9ac54";
+    /// DES of `hunter2!` under `8bytekey`
+    const LEGACY_HUNTER2: &str = "78d703065052558e";
+
+    fn set_legacy_key() {
+        std::env::set_var("DOUYIN_SECRET_USERS_DES_KEY", "8bytekey");
+    }
+
+    #[test]
+    fn legacy_short_password_hash_requires_reset() {
+        set_legacy_key();
+
+        for password in ["pw", "password", "password123"] {
+            assert_eq!(verify(password, LEGACY_SHORT), Ok(Verified::ResetRequired));
+        }
+    }
+
+    #[test]
+    fn legacy_hash_checks_the_first_eight_bytes() {
+        set_legacy_key();
+
+        assert_eq!(verify("hunter2!", LEGACY_HUNTER2), Ok(Verified::ValidNeedsRehash));
+        assert_eq!(verify("hunter2!xyz", LEGACY_HUNTER2), Ok(Verified::ValidNeedsRehash));
+        assert_eq!(verify("hunter3!", LEGACY_HUNTER2), Ok(Verified::Invalid));
+        assert_eq!(verify("hunter", LEGACY_HUNTER2), Ok(Verified::Invalid));
+    }
+
+    #[test]
+    fn phc_round_trip() {
+        let stored = hash("correct horse battery staple").unwrap();
+
+        assert!(stored.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert_eq!(verify("correct horse battery staple", &stored), Ok(Verified::Valid));
+        assert_eq!(verify("correct horse", &stored), Ok(Verified::Invalid));
+        assert_ne!(hash("correct horse battery staple").unwrap(), stored);
+    }
+
+    #[test]
+    fn other_costs_need_rehash() {
+        let cheap = PasswordHashSettings {
+            memory_kib: 8 * 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let stored = argon2(&cheap)
+            .unwrap()
+            .hash_password(b"hunter2", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+
+        assert_eq!(verify("hunter2", &stored), Ok(Verified::ValidNeedsRehash));
+        assert_eq!(verify("hunter3", &stored), Ok(Verified::Invalid));
+    }
+}
@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Limits shared by every host. The adaptive state of each host starts from
+/// these values and never exceeds them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests_per_second: f64,
+    pub burst: u32,
+    pub max_concurrency: usize,
+    /// Floor the rate is never cut below when throttling is detected
+    pub min_requests_per_second: f64,
+    /// Consecutive successful requests needed before stepping back up
+    pub recovery_successes: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: 2.0,
+            burst: 4,
+            max_concurrency: 4,
+            min_requests_per_second: 0.1,
+            recovery_successes: 10,
+        }
+    }
+}
+
+/// Current limiter state of one host, as shown in the UI
+#[derive(Debug, Clone, Serialize)]
+pub struct HostLimitState {
+    pub host: String,
+    pub requests_per_second: f64,
+    pub max_concurrency: usize,
+    pub in_flight: usize,
+    pub tokens: f64,
+    pub throttle_count: u64,
+    pub last_throttled_at: Option<String>,
+}
+
+struct HostLimiter {
+    rate: f64,
+    concurrency: usize,
+    tokens: f64,
+    last_refill: Instant,
+    in_flight: usize,
+    success_streak: u32,
+    throttle_count: u64,
+    last_throttled_at: Option<String>,
+    released: Arc<Notify>,
+}
+
+impl HostLimiter {
+    fn new(config: &RateLimitConfig) -> Self {
+        HostLimiter {
+            rate: config.requests_per_second,
+            concurrency: config.max_concurrency.max(1),
+            tokens: config.burst as f64,
+            last_refill: Instant::now(),
+            in_flight: 0,
+            success_streak: 0,
+            throttle_count: 0,
+            last_throttled_at: None,
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    fn refill(&mut self, config: &RateLimitConfig) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(config.burst.max(1) as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes a token and a concurrency slot, or returns how long to wait
+    fn try_take(&mut self, config: &RateLimitConfig) -> Result<(), Duration> {
+        self.refill(config);
+
+        if self.in_flight >= self.concurrency {
+            return Err(Duration::from_millis(500));
+        }
+
+        if self.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate));
+        }
+
+        self.tokens -= 1.0;
+        self.in_flight += 1;
+
+        Ok(())
+    }
+
+    fn throttled(&mut self, config: &RateLimitConfig) {
+        self.rate = (self.rate / 2.0).max(config.min_requests_per_second);
+        self.concurrency = (self.concurrency / 2).max(1);
+        self.tokens = 0.0;
+        self.success_streak = 0;
+        self.throttle_count += 1;
+        self.last_throttled_at = Some(chrono::Local::now().to_rfc3339());
+    }
+
+    fn succeeded(&mut self, config: &RateLimitConfig) {
+        if self.rate >= config.requests_per_second && self.concurrency >= config.max_concurrency {
+            return;
+        }
+
+        self.success_streak += 1;
+
+        if self.success_streak >= config.recovery_successes {
+            self.rate = (self.rate * 1.5).min(config.requests_per_second);
+            self.concurrency = (self.concurrency + 1).min(config.max_concurrency.max(1));
+            self.success_streak = 0;
+        }
+    }
+}
+
+struct Limiters {
+    config: RateLimitConfig,
+    hosts: HashMap<String, HostLimiter>,
+}
+
+fn limiters() -> &'static Mutex<Limiters> {
+    static LIMITERS: OnceLock<Mutex<Limiters>> = OnceLock::new();
+    LIMITERS.get_or_init(|| {
+        Mutex::new(Limiters {
+            config: RateLimitConfig::default(),
+            hosts: HashMap::new(),
+        })
+    })
+}
+
+/// A granted request slot. Dropping it frees the concurrency slot; report the
+/// outcome first so the host's rate can adapt.
+pub struct Permit {
+    host: String,
+}
+
+impl Permit {
+    /// The response was a 429, a captcha page or an otherwise empty answer
+    pub fn throttled(&self) {
+        record_throttle(&self.host);
+    }
+
+    pub fn succeeded(&self) {
+        let mut limiters = limiters().lock().unwrap();
+        let Limiters { config, hosts } = &mut *limiters;
+
+        if let Some(limiter) = hosts.get_mut(&self.host) {
+            limiter.succeeded(config);
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut limiters = limiters().lock().unwrap();
+
+        if let Some(limiter) = limiters.hosts.get_mut(&self.host) {
+            limiter.in_flight = limiter.in_flight.saturating_sub(1);
+            limiter.released.notify_waiters();
+        }
+    }
+}
+
+/// Waits until `host` may be contacted again
+pub async fn acquire(host: &str) -> Permit {
+    loop {
+        let (wait, released) = {
+            let mut limiters = limiters().lock().unwrap();
+            let Limiters { config, hosts } = &mut *limiters;
+            let limiter = hosts
+                .entry(host.to_string())
+                .or_insert_with(|| HostLimiter::new(config));
+
+            match limiter.try_take(config) {
+                Ok(_) => {
+                    return Permit {
+                        host: host.to_string(),
+                    }
+                }
+                Err(wait) => (wait, limiter.released.clone()),
+            }
+        };
+
+        let _ = tokio::time::timeout(wait, released.notified()).await;
+    }
+}
+
+/// Reports throttling seen outside a request, e.g. an empty `aweme_list`
+pub fn record_throttle(host: &str) {
+    let mut limiters = limiters().lock().unwrap();
+    let Limiters { config, hosts } = &mut *limiters;
+
+    hosts
+        .entry(host.to_string())
+        .or_insert_with(|| HostLimiter::new(config))
+        .throttled(config);
+}
+
+/// Returns a snapshot of every host seen so far
+pub fn current_state() -> Vec<HostLimitState> {
+    let mut limiters = limiters().lock().unwrap();
+    let Limiters { config, hosts } = &mut *limiters;
+    let mut states = hosts
+        .iter_mut()
+        .map(|(host, limiter)| {
+            limiter.refill(config);
+
+            HostLimitState {
+                host: host.clone(),
+                requests_per_second: limiter.rate,
+                max_concurrency: limiter.concurrency,
+                in_flight: limiter.in_flight,
+                tokens: limiter.tokens,
+                throttle_count: limiter.throttle_count,
+                last_throttled_at: limiter.last_throttled_at.clone(),
+            }
+        })
+        .collect::<Vec<HostLimitState>>();
+
+    states.sort_by(|a, b| a.host.cmp(&b.host));
+
+    states
+}
+
+pub fn current_config() -> RateLimitConfig {
+    limiters().lock().unwrap().config.clone()
+}
+
+/// Replaces the limits and resets every host to them
+pub fn update_config(config: RateLimitConfig) -> Result<(), String> {
+    if config.requests_per_second <= 0.0
+        || config.min_requests_per_second <= 0.0
+        || config.min_requests_per_second > config.requests_per_second
+    {
+        return Err("请求频率配置无效".into());
+    }
+
+    let mut limiters = limiters().lock().unwrap();
+
+    for limiter in limiters.hosts.values_mut() {
+        limiter.rate = config.requests_per_second;
+        limiter.concurrency = config.max_concurrency.max(1);
+        limiter.tokens = limiter.tokens.min(config.burst as f64);
+        limiter.success_streak = 0;
+        limiter.released.notify_waiters();
+    }
+
+    limiters.config = config;
+
+    Ok(())
+}
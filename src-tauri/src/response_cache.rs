@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+use crate::file_finalizer::{self, ConflictPolicy};
+
+/// The metadata lookups that are cached, each with its own TTL and subdirectory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    UserInfo,
+    VideoInfo,
+    PostList,
+}
+
+impl CacheKind {
+    pub const ALL: [CacheKind; 3] = [
+        CacheKind::UserInfo,
+        CacheKind::VideoInfo,
+        CacheKind::PostList,
+    ];
+
+    fn dir_name(&self) -> &'static str {
+        match self {
+            CacheKind::UserInfo => "user_info",
+            CacheKind::VideoInfo => "video_info",
+            CacheKind::PostList => "post_list",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub user_info_ttl_secs: u64,
+    pub video_info_ttl_secs: u64,
+    pub post_list_ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            user_info_ttl_secs: 60 * 60,
+            video_info_ttl_secs: 24 * 60 * 60,
+            post_list_ttl_secs: 10 * 60,
+        }
+    }
+}
+
+impl CacheConfig {
+    fn ttl(&self, kind: CacheKind) -> u64 {
+        match kind {
+            CacheKind::UserInfo => self.user_info_ttl_secs,
+            CacheKind::VideoInfo => self.video_info_ttl_secs,
+            CacheKind::PostList => self.post_list_ttl_secs,
+        }
+    }
+}
+
+/// Entry counts and sizes per kind, for the cache inspector
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub dir: String,
+    pub kinds: Vec<CacheKindStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheKindStats {
+    pub kind: CacheKind,
+    pub entries: u64,
+    pub expired: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    stored_at: u64,
+    value: serde_json::Value,
+}
+
+struct Cache {
+    dir: PathBuf,
+    config: CacheConfig,
+}
+
+fn cache() -> &'static RwLock<Cache> {
+    static CACHE: OnceLock<RwLock<Cache>> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        RwLock::new(Cache {
+            dir: std::env::temp_dir()
+                .join("douyin-downloader")
+                .join("response_cache"),
+            config: CacheConfig::default(),
+        })
+    })
+}
+
+/// Points the cache at the app cache directory; called once at startup
+pub fn init(dir: PathBuf) {
+    cache().write().unwrap().dir = dir.join("response_cache");
+}
+
+pub fn current_config() -> CacheConfig {
+    cache().read().unwrap().config.clone()
+}
+
+pub fn update_config(config: CacheConfig) {
+    cache().write().unwrap().config = config;
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Named by a hash that stays the same across Rust releases, so the cache
+/// survives a toolchain upgrade
+fn entry_path(dir: &Path, kind: CacheKind, key: &str) -> PathBuf {
+    let digest = Sha256::digest(key.as_bytes());
+
+    dir.join(kind.dir_name())
+        .join(format!("{}.json", hex::encode(&digest[..8])))
+}
+
+/// Returns the cached value for `key` if present and younger than the kind's TTL
+pub fn get(kind: CacheKind, key: &str) -> Option<serde_json::Value> {
+    let cache = cache().read().unwrap();
+
+    if !cache.config.enabled {
+        return None;
+    }
+
+    let content = fs::read(entry_path(&cache.dir, kind, key)).ok()?;
+    let entry = serde_json::from_slice::<CacheEntry>(&content).ok()?;
+
+    // A hash collision shows up as a different key and counts as a miss
+    if entry.key != key || now_secs().saturating_sub(entry.stored_at) > cache.config.ttl(kind) {
+        return None;
+    }
+
+    Some(entry.value)
+}
+
+/// Stores `value` under `key`. Cache write failures are not fatal to the lookup.
+pub fn put(kind: CacheKind, key: &str, value: &serde_json::Value) {
+    let cache = cache().read().unwrap();
+
+    if !cache.config.enabled {
+        return;
+    }
+
+    let path = entry_path(&cache.dir, kind, key);
+    let entry = CacheEntry {
+        key: key.to_string(),
+        stored_at: now_secs(),
+        value: value.clone(),
+    };
+
+    let (Some(parent), Ok(content)) = (path.parent(), serde_json::to_vec(&entry)) else {
+        return;
+    };
+    let temp = file_finalizer::temp_path_for(&path);
+    let written = fs::create_dir_all(parent)
+        .and_then(|_| fs::write(&temp, content))
+        .map_err(|_| "缓存写入失败".to_string())
+        .and_then(|_| file_finalizer::finalize(&temp, &path, ConflictPolicy::Overwrite));
+
+    if written.is_err() {
+        file_finalizer::discard(&temp);
+    }
+}
+
+pub fn stats() -> CacheStats {
+    let cache = cache().read().unwrap();
+    let now = now_secs();
+    let kinds = CacheKind::ALL
+        .iter()
+        .map(|kind| {
+            let mut stats = CacheKindStats {
+                kind: *kind,
+                entries: 0,
+                expired: 0,
+                bytes: 0,
+            };
+
+            for entry in fs::read_dir(cache.dir.join(kind.dir_name()))
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let content = fs::read(entry.path()).unwrap_or_default();
+                let stored_at = serde_json::from_slice::<CacheEntry>(&content)
+                    .map(|entry| entry.stored_at)
+                    .unwrap_or(0);
+
+                stats.entries += 1;
+                stats.bytes += content.len() as u64;
+
+                if now.saturating_sub(stored_at) > cache.config.ttl(*kind) {
+                    stats.expired += 1;
+                }
+            }
+
+            stats
+        })
+        .collect();
+
+    CacheStats {
+        dir: cache.dir.to_string_lossy().to_string(),
+        kinds,
+    }
+}
+
+/// Removes every entry of `kind`, or of all kinds; returns the number removed
+pub fn clear(kind: Option<CacheKind>) -> Result<u64, String> {
+    let cache = cache().read().unwrap();
+    let mut removed = 0_u64;
+
+    for kind in CacheKind::ALL
+        .iter()
+        .filter(|item| kind.is_none() || kind == Some(**item))
+    {
+        for entry in fs::read_dir(cache.dir.join(kind.dir_name()))
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            fs::remove_file(entry.path()).map_err(|_| "缓存删除失败")?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
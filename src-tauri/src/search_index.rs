@@ -0,0 +1,212 @@
+use jieba_rs::Jieba;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::library::{self, LibraryItem};
+
+/// BM25 term frequency saturation and length normalization
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Largest page `search` returns
+const MAX_PAGE_SIZE: usize = 500;
+
+/// How much a term counts depending on where it was found; hashtags and
+/// authors are deliberate labels, captions are mostly noise
+const TITLE_WEIGHT: f32 = 1.0;
+const TAG_WEIGHT: f32 = 2.0;
+const AUTHOR_WEIGHT: f32 = 1.5;
+const MUSIC_WEIGHT: f32 = 0.8;
+
+/// Share of a Chinese word's weight given to each of its characters, so a
+/// one-character query still finds longer words
+const CHARACTER_WEIGHT: f32 = 0.3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        SearchQuery {
+            text: String::new(),
+            offset: 0,
+            limit: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub score: f32,
+    pub item: LibraryItem,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchPage {
+    /// Matching items before paging
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+/// Inverted index over the library items of one generation
+struct Index {
+    generation: u64,
+    items: Vec<LibraryItem>,
+    /// Weighted length of every item
+    lengths: Vec<f32>,
+    average_length: f32,
+    /// Term to (item, weighted frequency)
+    postings: HashMap<String, Vec<(u32, f32)>>,
+}
+
+fn jieba() -> &'static Jieba {
+    static JIEBA: OnceLock<Jieba> = OnceLock::new();
+    JIEBA.get_or_init(Jieba::new)
+}
+
+fn index_cache() -> &'static Mutex<Option<Arc<Index>>> {
+    static INDEX: OnceLock<Mutex<Option<Arc<Index>>>> = OnceLock::new();
+    INDEX.get_or_init(|| Mutex::new(None))
+}
+
+/// Ranks library items against `query.text` with BM25. Items need to contain
+/// one of the query terms; those containing more of them rank higher. The
+/// index is rebuilt from the library whenever the library has changed.
+pub fn search(query: &SearchQuery) -> SearchPage {
+    let index = current_index();
+    let mut terms = tokenize(&query.text);
+
+    terms.sort();
+    terms.dedup();
+
+    let mut scores = HashMap::<u32, (f32, usize)>::new();
+
+    for term in &terms {
+        let Some(postings) = index.postings.get(term) else {
+            continue;
+        };
+        let matched = postings.len() as f32;
+        let idf = ((index.items.len() as f32 - matched + 0.5) / (matched + 0.5) + 1.0).ln();
+
+        for (doc, frequency) in postings {
+            let length = index.lengths[*doc as usize] / index.average_length.max(1.0);
+            let score = idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length));
+            let entry = scores.entry(*doc).or_default();
+
+            entry.0 += score;
+            entry.1 += 1;
+        }
+    }
+
+    let mut ranked = scores
+        .into_iter()
+        .map(|(doc, (score, matched))| (doc, score * matched as f32 / terms.len() as f32))
+        .collect::<Vec<(u32, f32)>>();
+
+    // Newer items first among equal scores, then by path so pages stay stable
+    ranked.sort_by(|(a, a_score), (b, b_score)| {
+        let (a, b) = (&index.items[*a as usize], &index.items[*b as usize]);
+
+        b_score
+            .total_cmp(a_score)
+            .then_with(|| b.date().cmp(&a.date()))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    SearchPage {
+        total: ranked.len(),
+        hits: ranked
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.clamp(1, MAX_PAGE_SIZE))
+            .map(|(doc, score)| SearchHit {
+                score,
+                item: index.items[doc as usize].clone(),
+            })
+            .collect(),
+    }
+}
+
+/// The cached index, rebuilt from a fresh copy of the items only when the
+/// library changed since it was built
+fn current_index() -> Arc<Index> {
+    let mut cache = index_cache().lock().unwrap();
+
+    if let Some(index) = cache.as_ref().filter(|index| index.generation == library::generation()) {
+        return index.clone();
+    }
+
+    let (generation, items) = library::snapshot();
+    let index = Arc::new(build(generation, items));
+
+    *cache = Some(index.clone());
+    index
+}
+
+fn build(generation: u64, items: Vec<LibraryItem>) -> Index {
+    let mut postings = HashMap::<String, Vec<(u32, f32)>>::new();
+    let mut lengths = Vec::with_capacity(items.len());
+
+    for (doc, item) in items.iter().enumerate() {
+        let mut frequencies = HashMap::<String, f32>::new();
+        let fields = [
+            (item.title.as_str(), TITLE_WEIGHT),
+            (&item.tags.join(" "), TAG_WEIGHT),
+            (&item.author_nickname, AUTHOR_WEIGHT),
+            (&item.music_title, MUSIC_WEIGHT),
+        ];
+        let mut length = 0.0;
+
+        for (text, weight) in fields {
+            for term in tokenize(text) {
+                if term.chars().count() > 1 && term.chars().all(is_cjk) {
+                    for character in term.chars() {
+                        *frequencies.entry(character.to_string()).or_default() +=
+                            weight * CHARACTER_WEIGHT;
+                        length += weight * CHARACTER_WEIGHT;
+                    }
+                }
+
+                *frequencies.entry(term).or_default() += weight;
+                length += weight;
+            }
+        }
+
+        for (term, frequency) in frequencies {
+            postings.entry(term).or_default().push((doc as u32, frequency));
+        }
+
+        lengths.push(length);
+    }
+
+    let average_length = lengths.iter().sum::<f32>() / lengths.len().max(1) as f32;
+
+    Index {
+        generation,
+        items,
+        lengths,
+        average_length,
+        postings,
+    }
+}
+
+/// Lowercase terms of `text`. Chinese is split into words and, for longer
+/// words, their sub-words too, so `猫咪` also finds `小猫咪`.
+fn tokenize(text: &str) -> Vec<String> {
+    jieba()
+        .cut_for_search(text, true)
+        .into_iter()
+        .filter(|token| token.chars().any(char::is_alphanumeric))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn is_cjk(character: char) -> bool {
+    matches!(character, '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}')
+}
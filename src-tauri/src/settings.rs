@@ -0,0 +1,420 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+use crate::file_finalizer::{self, ConflictPolicy};
+use crate::ftp_upload::{self, FtpConfig};
+use crate::library::LibraryConfig;
+use crate::passwords;
+use crate::s3_upload::{self, S3Config};
+use crate::secrets::{self, SecretsConfig};
+
+/// Name of the settings file in the app config dir
+const SETTINGS_FILE: &str = "settings.toml";
+
+/// Environment variables named `DOUYIN_<SECTION>_<KEY>` override the file,
+/// e.g. `DOUYIN_USERS_API_PORT=3002` for `[users_api] port`. Lists are split
+/// like `PATH`, e.g. `DOUYIN_LIBRARY_ROOTS=/media/a:/media/b`.
+const ENV_PREFIX: &str = "DOUYIN_";
+
+/// Addresses of the local services and the backends they talk to. Each
+/// value comes from the built-in default, then `settings.toml`, then the
+/// environment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Its login cookie is `Secure`; put it behind TLS unless it is only
+    /// reached through `localhost`
+    pub users_api: ServerSettings,
+    /// The Rocket service
+    pub http_service: ServerSettings,
+    pub mongo: MongoSettings,
+    pub redis: RedisSettings,
+    pub ldap: LdapSettings,
+    pub mssql: MssqlSettings,
+    /// Costs for new password hashes of the users API
+    pub password_hash: PasswordHashSettings,
+    /// Login sessions of the users API
+    pub sessions: SessionSettings,
+    /// Where finished downloads are uploaded over FTP
+    pub ftp: FtpConfig,
+    /// Where finished downloads are uploaded to S3
+    pub s3: S3Config,
+    /// Folders indexed by the media library
+    pub library: LibraryConfig,
+    /// Where passwords and keys are looked up
+    pub secrets: SecretsConfig,
+}
+
+/// Where a local HTTP service listens, if it runs at all. Changes apply
+/// after a restart.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSettings {
+    pub enabled: bool,
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+/// Read when the users API starts; changes apply after a restart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MongoSettings {
+    /// `mongodb://` or `mongodb+srv://` connection string, without a password
+    pub uri: String,
+    pub database: String,
+    /// Logs in with the `mongo.password` secret when set
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisSettings {
+    pub host: String,
+    pub port: u16,
+    pub db: i64,
+    /// Logs in with the `redis.password` secret
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LdapSettings {
+    /// `ldap://` or `ldaps://` url of the directory server
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MssqlSettings {
+    pub host: String,
+    pub port: u16,
+    pub database: String,
+    /// Logs in with the `mssql.password` secret
+    pub username: String,
+}
+
+/// Argon2id costs. Existing hashes keep working when these change and are
+/// rehashed on the next successful login.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordHashSettings {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionSettings {
+    /// A session without requests for this long is gone. Applies after a
+    /// restart.
+    pub idle_minutes: u32,
+    /// Log in again after this long, however active the session is
+    pub max_hours: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            // Both services are off until enabled in `settings.toml`
+            users_api: ServerSettings {
+                enabled: false,
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 3001,
+            },
+            // Rocket's own defaults
+            http_service: ServerSettings {
+                enabled: false,
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 8000,
+            },
+            mongo: MongoSettings {
+                uri: "mongodb://localhost:27017/?authSource=admin".into(),
+                database: "userdb".into(),
+                username: String::new(),
+            },
+            redis: RedisSettings {
+                host: "production-redis-cluster.internal".into(),
+                port: 6379,
+                db: 0,
+                username: "admin".into(),
+            },
+            ldap: LdapSettings {
+                url: "ldap://localhost:389".into(),
+            },
+            mssql: MssqlSettings {
+                host: "localhost".into(),
+                port: 1433,
+                database: "test_db".into(),
+                username: "sa".into(),
+            },
+            // OWASP's minimum for Argon2id
+            password_hash: PasswordHashSettings {
+                memory_kib: 19 * 1024,
+                iterations: 2,
+                parallelism: 1,
+            },
+            sessions: SessionSettings {
+                idle_minutes: 30,
+                max_hours: 12,
+            },
+            ftp: FtpConfig::default(),
+            s3: S3Config::default(),
+            library: LibraryConfig::default(),
+            secrets: SecretsConfig::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    /// Settings file, unknown until `init`
+    path: Option<PathBuf>,
+    /// The keys set in the file as last loaded, without defaults or
+    /// environment overrides
+    file: toml::Table,
+    settings: Settings,
+    /// The settings the app started with, to tell when a restart is needed
+    started: Option<Settings>,
+}
+
+fn store() -> &'static RwLock<Store> {
+    static STORE: OnceLock<RwLock<Store>> = OnceLock::new();
+    STORE.get_or_init(|| RwLock::new(Store::default()))
+}
+
+/// Loads `settings.toml` from `dir`. Fails when the file or an environment
+/// override is invalid, so the app does not start with half a configuration.
+pub fn init(dir: PathBuf) -> Result<(), String> {
+    let path = dir.join(SETTINGS_FILE);
+    let file = read_file(&path)?;
+    let settings = resolve(&file)?;
+    let mut store = store().write().unwrap();
+
+    store.started = Some(settings.clone());
+    store.path = Some(path);
+    store.file = file;
+    store.settings = settings;
+
+    Ok(())
+}
+
+pub fn current() -> Settings {
+    store().read().unwrap().settings.clone()
+}
+
+/// Saves the fields of `settings` that differ from `current()` to the file
+/// and applies them. Values that only come from the defaults or the
+/// environment are not written, and environment overrides still take
+/// precedence. Returns whether a restart is needed for the change to take
+/// effect; everything else applies to the next connection.
+pub fn update(settings: Settings) -> Result<bool, String> {
+    validate(&settings)?;
+
+    let mut store = store().write().unwrap();
+    let path = store.path.clone().ok_or("配置目录不可用")?;
+    let shown = toml::Table::try_from(&store.settings).map_err(|_| "序列化失败")?;
+    let edited = toml::Table::try_from(&settings).map_err(|_| "序列化失败")?;
+    let mut file = store.file.clone();
+
+    overlay_edits(&mut file, &shown, edited);
+
+    let settings = resolve(&file)?;
+    let content = toml::to_string_pretty(&file).map_err(|_| "序列化失败")?;
+    let temp = file_finalizer::temp_path_for(&path);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|_| "目录创建失败")?;
+    }
+
+    fs::write(&temp, content).map_err(|_| "配置文件写入失败")?;
+    file_finalizer::finalize(&temp, &path, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))?;
+
+    store.file = file;
+    store.settings = settings;
+
+    Ok(restart_required(&store))
+}
+
+/// Reads the file and the environment again, keeping the current settings
+/// if either is invalid. Returns whether a server has to be restarted.
+pub fn reload() -> Result<bool, String> {
+    let mut store = store().write().unwrap();
+    let path = store.path.clone().ok_or("配置目录不可用")?;
+
+    let file = read_file(&path)?;
+
+    store.settings = resolve(&file)?;
+    store.file = file;
+
+    Ok(restart_required(&store))
+}
+
+/// The servers, and what the users API reads once when it starts
+fn restart_required(store: &Store) -> bool {
+    let current = &store.settings;
+
+    store.started.as_ref().is_some_and(|started| {
+        started.users_api != current.users_api
+            || started.http_service != current.http_service
+            || started.mongo != current.mongo
+            || started.sessions.idle_minutes != current.sessions.idle_minutes
+    })
+}
+
+/// Only the keys set in the file, empty when there is no file yet
+fn read_file(path: &Path) -> Result<toml::Table, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content)
+            .map_err(|e| format!("配置文件格式错误: {}", e.to_string().trim_end())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(_) => Err("配置文件读取失败".into()),
+    }
+}
+
+/// The defaults, overlaid with `file` and then the environment
+fn resolve(file: &toml::Table) -> Result<Settings, String> {
+    let mut table = toml::Table::try_from(Settings::default()).map_err(|_| "序列化失败")?;
+
+    merge(&mut table, file.clone());
+    apply_env(&mut table)?;
+
+    let settings = table
+        .try_into::<Settings>()
+        .map_err(|e| format!("配置无效: {}", e.to_string().trim_end()))?;
+
+    validate(&settings)?;
+
+    Ok(settings)
+}
+
+/// Sections present in `overlay` only replace the keys they contain
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Copies into `file` the keys whose value in `edited` differs from `shown`
+fn overlay_edits(file: &mut toml::Table, shown: &toml::Table, edited: toml::Table) {
+    for (key, value) in edited {
+        let before = shown.get(&key);
+
+        if before == Some(&value) {
+            continue;
+        }
+
+        match (value, before) {
+            (toml::Value::Table(value), Some(toml::Value::Table(before))) => {
+                if !matches!(file.get(&key), Some(toml::Value::Table(_))) {
+                    file.insert(key.clone(), toml::Value::Table(toml::Table::new()));
+                }
+
+                if let Some(toml::Value::Table(section)) = file.get_mut(&key) {
+                    overlay_edits(section, before, value);
+                }
+            }
+            (value, _) => {
+                file.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replaces every key that has a `DOUYIN_<SECTION>_<KEY>` variable, parsed
+/// as the type the key already has
+fn apply_env(table: &mut toml::Table) -> Result<(), String> {
+    for (section, fields) in table.iter_mut() {
+        let Some(fields) = fields.as_table_mut() else {
+            continue;
+        };
+
+        for (key, value) in fields.iter_mut() {
+            let name = format!("{}{}_{}", ENV_PREFIX, section, key).to_uppercase();
+            let Ok(raw) = std::env::var(&name) else {
+                continue;
+            };
+            let parsed = match value {
+                toml::Value::Integer(_) => raw.trim().parse().ok().map(toml::Value::Integer),
+                toml::Value::Boolean(_) => raw.trim().parse().ok().map(toml::Value::Boolean),
+                toml::Value::Array(_) => Some(toml::Value::Array(
+                    std::env::split_paths(&raw)
+                        .filter(|item| !item.as_os_str().is_empty())
+                        .map(|item| toml::Value::String(item.to_string_lossy().to_string()))
+                        .collect(),
+                )),
+                _ => Some(toml::Value::String(raw)),
+            };
+
+            *value = parsed.ok_or_else(|| format!("环境变量 {} 无效", name))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn validate(settings: &Settings) -> Result<(), String> {
+    for (name, server) in [("users_api", &settings.users_api), ("http_service", &settings.http_service)] {
+        if server.port == 0 {
+            return Err(format!("{} 端口无效", name));
+        }
+    }
+
+    if !["mongodb://", "mongodb+srv://"]
+        .iter()
+        .any(|scheme| settings.mongo.uri.starts_with(scheme))
+    {
+        return Err("MongoDB 连接串无效".into());
+    }
+
+    // Passwords belong in the secret store, where they are kept out of logs
+    if secrets::mask_userinfo(&settings.mongo.uri) != settings.mongo.uri {
+        return Err("MongoDB 连接串中不能包含密码，请使用 mongo.password 密钥".into());
+    }
+
+    if !["ldap://", "ldaps://"]
+        .iter()
+        .any(|scheme| settings.ldap.url.starts_with(scheme))
+    {
+        return Err("LDAP 地址无效".into());
+    }
+
+    if settings.mongo.database.is_empty() || settings.mssql.database.is_empty() {
+        return Err("数据库名不能为空".into());
+    }
+
+    for (name, host, port) in [
+        ("Redis", &settings.redis.host, settings.redis.port),
+        ("MSSQL", &settings.mssql.host, settings.mssql.port),
+    ] {
+        if host.is_empty() || port == 0 {
+            return Err(format!("{} 地址无效", name));
+        }
+    }
+
+    if settings.redis.db < 0 {
+        return Err("Redis 数据库编号无效".into());
+    }
+
+    passwords::argon2(&settings.password_hash)?;
+
+    if settings.sessions.idle_minutes == 0 || settings.sessions.max_hours == 0 {
+        return Err("会话有效期必须大于 0".into());
+    }
+
+    ftp_upload::validate(&settings.ftp)?;
+    s3_upload::validate(&settings.s3)?;
+    secrets::validate(&settings.secrets)?;
+
+    Ok(())
+}
@@ -2,6 +2,9 @@ use std::net::SocketAddr;
 use tiberius::{Client, Config, AuthMethod};
 use tokio_util::compat::TokioAsyncWriteCompatExt;
 
+use crate::secrets;
+use crate::settings;
+
 /// Handles SQL execution processing
 pub fn handle_sql_execution(raw_sql: String, source_addr: SocketAddr) {
     let parsed_sql = parse_database_query(raw_sql, source_addr);
@@ -83,11 +86,19 @@ fn prepare_query_execution(enriched_sql: String) -> String {
 /// Executes database operations using tiberius
 async fn execute_database_operations(tainted_sql: String) {
     // Create database configuration
+    let mssql = settings::current().mssql;
+    let password = match secrets::require(secrets::MSSQL_PASSWORD) {
+        Ok(password) => password,
+        Err(e) => {
+            eprintln!("MSSQL login skipped: {}", e);
+            return;
+        }
+    };
     let mut config = Config::new();
-    config.host("localhost");
-    config.port(1433);
-    config.database("test_db");
-    config.authentication(AuthMethod::sql_server("sa", "password"));
+    config.host(&mssql.host);
+    config.port(mssql.port);
+    config.database(&mssql.database);
+    config.authentication(AuthMethod::sql_server(&mssql.username, &password));
     config.trust_cert();
     
     // Attempt to connect to database
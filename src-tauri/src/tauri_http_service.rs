@@ -18,6 +18,8 @@ use nix::unistd::{chown, Gid, Uid};
 
 use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
 
+use crate::settings;
+
 #[derive(Debug, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ApiResponse {
@@ -253,6 +255,13 @@ pub fn verify_ssl() -> Json<ApiResponse> {
     })
 }
 
+/// Listens where `[http_service]` says; these take precedence over
+/// `Rocket.toml` and `ROCKET_*` variables
 pub fn create_rocket() -> Rocket<Build> {
-    rocket::build().mount("/", routes![refresh_token, code_eval, load_wasm_file, string_manipulation, calculate_remainder, allocate_resources, permission_update, verify_ssl])
+    let server = settings::current().http_service;
+    let figment = rocket::Config::figment()
+        .merge(("address", server.address))
+        .merge(("port", server.port));
+
+    rocket::custom(figment).mount("/", routes![refresh_token, code_eval, load_wasm_file, string_manipulation, calculate_remainder, allocate_resources, permission_update, verify_ssl])
 }
@@ -0,0 +1,149 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+
+/// `Live` talks to the network only, `Record` talks to the network and saves
+/// every exchange, `Replay` never touches the network and serves saved exchanges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficMode {
+    Live,
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficConfig {
+    pub mode: TrafficMode,
+    pub fixture_dir: Option<String>,
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        TrafficConfig {
+            mode: TrafficMode::Live,
+            fixture_dir: None,
+        }
+    }
+}
+
+/// One recorded exchange. Media bodies are never recorded, only the metadata
+/// requests made by the command layer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    pub body: String,
+    pub recorded_at: String,
+}
+
+fn traffic_config() -> &'static RwLock<TrafficConfig> {
+    static CONFIG: OnceLock<RwLock<TrafficConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| RwLock::new(TrafficConfig::default()))
+}
+
+pub fn current_config() -> TrafficConfig {
+    traffic_config().read().unwrap().clone()
+}
+
+/// Switches the mode; recording and replaying both need a fixture directory
+pub fn update_config(config: TrafficConfig) -> Result<(), String> {
+    if config.mode != TrafficMode::Live {
+        let dir = config.fixture_dir.as_deref().unwrap_or("");
+
+        if dir.is_empty() {
+            return Err("未指定录制目录".into());
+        }
+
+        if config.mode == TrafficMode::Record {
+            fs::create_dir_all(dir).map_err(|_| "录制目录创建失败")?;
+        }
+    }
+
+    *traffic_config().write().unwrap() = config;
+
+    Ok(())
+}
+
+/// Query parameters are sorted so that the same request always maps to the
+/// same fixture regardless of how the url was assembled
+fn fixture_key(method: &str, url: &Url) -> String {
+    let mut url = url.clone();
+    let mut pairs = url
+        .query_pairs()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<Vec<(String, String)>>();
+
+    pairs.sort();
+    url.set_query(None);
+
+    if !pairs.is_empty() {
+        url.query_pairs_mut().extend_pairs(pairs);
+    }
+
+    format!("{} {}", method, url)
+}
+
+/// Named after a SHA-256 of the key, which unlike `DefaultHasher` stays the
+/// same across Rust releases
+fn fixture_path(dir: &str, key: &str, url: &Url) -> PathBuf {
+    let digest = Sha256::digest(key.as_bytes());
+    let name = url
+        .path()
+        .trim_matches('/')
+        .replace(|item: char| !item.is_ascii_alphanumeric(), "_");
+
+    PathBuf::from(dir).join(format!(
+        "{}_{}_{}.json",
+        url.host_str().unwrap_or("local"),
+        name,
+        hex::encode(&digest[..8])
+    ))
+}
+
+/// In replay mode returns the recorded exchange for the request, or an error
+/// naming the missing fixture. Outside replay mode returns `None`.
+pub fn replay(method: &str, url: &Url) -> Result<Option<Fixture>, String> {
+    let config = current_config();
+
+    if config.mode != TrafficMode::Replay {
+        return Ok(None);
+    }
+
+    let key = fixture_key(method, url);
+    let path = fixture_path(config.fixture_dir.as_deref().unwrap_or(""), &key, url);
+    let content = fs::read(&path).map_err(|_| format!("回放数据不存在: {}", key))?;
+    let fixture = serde_json::from_slice::<Fixture>(&content)
+        .map_err(|_| format!("回放数据损坏: {}", path.to_string_lossy()))?;
+
+    Ok(Some(fixture))
+}
+
+/// In record mode saves the exchange, overwriting an earlier recording of it
+pub fn record(method: &str, url: &Url, status: u16, body: &str) {
+    let config = current_config();
+
+    if config.mode != TrafficMode::Record {
+        return;
+    }
+
+    let key = fixture_key(method, url);
+    let path = fixture_path(config.fixture_dir.as_deref().unwrap_or(""), &key, url);
+    let fixture = Fixture {
+        method: method.to_string(),
+        url: url.to_string(),
+        status,
+        body: body.to_string(),
+        recorded_at: chrono::Local::now().to_rfc3339(),
+    };
+
+    if let Ok(content) = serde_json::to_vec_pretty(&fixture) {
+        if let Err(e) = fs::write(&path, content) {
+            eprintln!("Failed to record fixture {}: {}", path.to_string_lossy(), e);
+        }
+    }
+}
@@ -1,24 +1,30 @@
 use axum::{
-    extract::{Json, State},
+    extract::{Extension, Json, State},
     http::{StatusCode, header, HeaderMap},
     response::{IntoResponse, Response, Html as AxumHtml},
-    routing::{post, put},
+    routing::{get, post, put},
     Router,
 };
-use serde::Deserialize;
-use std::sync::Arc;
-use des::cipher::{BlockEncrypt, KeyInit};
-use des::Des;
-use generic_array::GenericArray;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tower_http::cors::{CorsLayer as AxumCorsLayer, AllowOrigin};
 use axum_session::SessionConfig;
-use tower_sessions::{SessionManagerLayer, MemoryStore, Session};
+use tower_sessions::cookie::time::{Duration as CookieDuration, OffsetDateTime};
+use tower_sessions::cookie::SameSite;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, SessionStore};
+use tower_sessions::{Expiry, SessionManagerLayer, MemoryStore, Session};
 use redis::Client as RedisClient;
 use actix_web::{HttpResponse, HttpResponseBuilder, http::StatusCode as ActixStatusCode, web::Html, body::BoxBody};
-use cookie::CookieBuilder;
-use rocket_session_store::memory::MemoryStore as RocketMemoryStore;
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use mongodb::bson::Bson;
 
+use crate::passwords::{self, Verified};
+use crate::secrets;
+use crate::settings;
 use crate::users_data::{UsersDatabase, UserDocument};
 
 #[derive(Debug, Deserialize)]
@@ -48,27 +54,48 @@ pub struct ListUsersPageRequest {
 
 pub type DbState = Arc<UsersDatabase>;
 
+/// Session key of the logged-in user
+const SESSION_USER: &str = "user";
+
+/// How often expired sessions are dropped from memory
+const SESSION_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionUser {
+    username: String,
+    /// Unix seconds
+    logged_in_at: i64,
+}
+
 pub async fn create_router() -> Result<Router, Box<dyn std::error::Error>> {
     let db = UsersDatabase::new().await?;
     let db_state: DbState = Arc::new(db);
+    let sessions = LoginSessions::default();
+
+    tokio::spawn(sessions.clone().purge_expired());
 
     Ok(Router::new()
         .route("/api/users/register", post(create_user))
         .route("/api/users/password", put(update_user_password))
         .route("/api/users/login_page", post(login_page))
+        .route("/api/users/logout", post(logout))
+        .route("/api/users/session", get(current_session))
         .route("/api/users/list_users_page", post(list_users_page))
-        .with_state(db_state))
+        .with_state(db_state)
+        .layer(Extension(sessions.clone()))
+        .layer(session_layer(sessions)))
 }
 
 async fn create_user(
     State(db): State<DbState>,
     Json(payload): Json<CreateUserRequest>,
 ) -> Response {
-    // CWE 327
-    //SOURCE
     let password = payload.password;
 
-    let password_hash = hash_password(&password);
+    let password_hash = match hash_off_thread(password).await {
+        Ok(password_hash) => password_hash,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
 
     // SOURCE
     //CWE 943
@@ -100,51 +127,97 @@ async fn create_user(
             }))).into_response()
         }
         Err(e) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create user: {}", e)).into_response()
+            (StatusCode::INTERNAL_SERVER_ERROR, secrets::redact(&format!("Failed to create user: {}", e))).into_response()
         }
     }
 }
 
+/// Changes the password of the logged-in user and ends their other sessions
 async fn update_user_password(
     State(db): State<DbState>,
+    Extension(sessions): Extension<LoginSessions>,
+    session: Session,
     Json(payload): Json<UpdatePasswordRequest>,
 ) -> Response {
-    // CWE 327
+    let Some(user) = logged_in_user(&session).await else {
+        return (StatusCode::UNAUTHORIZED, "Not logged in").into_response();
+    };
+
     // CWE 943
     //SOURCE
     let new_password = payload.new_password;
     let user_id = payload.user_id;
 
-    let password_hash = hash_password_v2(&new_password);
+    match db.find_credentials(&user.username).await {
+        Ok(Some(stored)) if same_id(&stored.id, &user_id) => {}
+        Ok(_) => return (StatusCode::FORBIDDEN, "Only your own password can be changed").into_response(),
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, secrets::redact(&format!("Update error: {}", e))).into_response();
+        }
+    }
+
+    let password_hash = match hash_off_thread(new_password).await {
+        Ok(password_hash) => password_hash,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
 
     match db.update_user_password(&user_id, &password_hash).await {
-        Ok(true) => {
-            // CWE 942
-            //SINK
-            AxumCorsLayer::very_permissive();
-
-            (StatusCode::OK, Json(serde_json::json!({
-                "message": "Password updated successfully"
-            }))).into_response()
-        }
+        Ok(true) => {}
         Ok(false) => {
-            (StatusCode::NOT_FOUND, "User not found").into_response()
+            return (StatusCode::NOT_FOUND, "User not found").into_response();
         }
         Err(e) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Update error: {}", e)).into_response()
+            return (StatusCode::INTERNAL_SERVER_ERROR, secrets::redact(&format!("Update error: {}", e))).into_response();
         }
     }
+
+    // Sessions opened with the old password end here; this one gets a fresh id
+    sessions.end_user_sessions(&user.username, session.id());
+
+    if let Err(e) = session.cycle_id().await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Session error: {}", e)).into_response();
+    }
+
+    // CWE 942
+    //SINK
+    AxumCorsLayer::very_permissive();
+
+    (StatusCode::OK, Json(serde_json::json!({
+        "message": "Password updated successfully"
+    }))).into_response()
 }
 
 async fn login_page(
-    State(_db): State<DbState>,
+    State(db): State<DbState>,
+    session: Session,
     Json(payload): Json<LoginPageRequest>,
 ) -> Response {
-    // CWE 79
-    //SOURCE
     let username = payload.username;
     let password = payload.password;
 
+    match authenticate(&db, &username, &password).await {
+        Ok(Verified::Valid | Verified::ValidNeedsRehash) => {}
+        Ok(Verified::Invalid) => return (StatusCode::UNAUTHORIZED, "Invalid username or password").into_response(),
+        Ok(Verified::ResetRequired) => {
+            return (StatusCode::FORBIDDEN, "Password reset required, please contact an administrator").into_response();
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, secrets::redact(&e)).into_response(),
+    }
+
+    let user = SessionUser {
+        username: username.clone(),
+        logged_in_at: chrono::Utc::now().timestamp(),
+    };
+    // A fresh id, so a session id planted before login is useless afterwards
+    let stored = match session.cycle_id().await {
+        Ok(()) => session.insert(SESSION_USER, user).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = stored {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Session error: {}", e)).into_response();
+    }
+
     let html = format!(
         r#"<!DOCTYPE html>
         <html>
@@ -154,40 +227,160 @@ async fn login_page(
         <body>
             <h1>Welcome, {}!</h1>
             <p>Your login was successful.</p>
-            <p>Debug info: password length is {}</p>
         </body>
         </html>"#,
-        username, password.len()
+        escape_html(&username)
     );
 
-    // CWE 1004
-    // CWE 614
-    //SOURCE
-    let session_value = format!("session_{}", username);
-    let token = format!("enc_{}", session_value);
+    AxumHtml::from(html).into_response()
+}
 
-    let http_only = false;
-    let secure = false;
+/// Ends the session on the server and clears the cookie
+async fn logout(session: Session) -> Response {
+    match session.flush().await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Session error: {}", e)).into_response(),
+    }
+}
 
-    let cookie_builder = CookieBuilder::new("session", token).build();
+/// Who the session belongs to
+async fn current_session(session: Session) -> Response {
+    match logged_in_user(&session).await {
+        Some(user) => Json(serde_json::json!({
+            "username": user.username,
+            "logged_in_at": user.logged_in_at,
+        }))
+        .into_response(),
+        None => (StatusCode::UNAUTHORIZED, "Not logged in").into_response(),
+    }
+}
 
-    let cookie = CookieBuilder::from(cookie_builder)
-        .http_only(http_only)
-        .secure(secure);
-    
-    // CWE 614
-    // CWE 1004
-    //SINK
-    let _: rocket_session_store::SessionStore<String> = rocket_session_store::SessionStore {
-        cookie_builder: cookie,
-        name: "session".to_string(),
-        duration: Duration::from_secs(3600),
-        store: Box::new(RocketMemoryStore::default()),
-    };
+/// The user of a logged-in session. Sessions older than `max_hours` are
+/// ended here even while they are kept alive by activity.
+async fn logged_in_user(session: &Session) -> Option<SessionUser> {
+    let user = session.get::<SessionUser>(SESSION_USER).await.ok()??;
+    let max_age = settings::current().sessions.max_hours as i64 * 3600;
 
-    // CWE 79
-    //SINK
-    AxumHtml::from(html).into_response()
+    if chrono::Utc::now().timestamp() - user.logged_in_at > max_age {
+        let _ = session.flush().await;
+        return None;
+    }
+
+    Some(user)
+}
+
+/// Sessions live in memory on the server; the cookie only carries the id.
+/// Idle sessions expire after `idle_minutes`.
+///
+/// The cookie is `Secure`, so browsers only send it back over HTTPS or to
+/// `localhost`. Reached over plain HTTP under any other name, for example
+/// through `users_api.address = "0.0.0.0"`, the API needs a TLS proxy in
+/// front of it or logins never stick.
+fn session_layer(store: LoginSessions) -> SessionManagerLayer<LoginSessions> {
+    let sessions = settings::current().sessions;
+
+    SessionManagerLayer::new(store)
+        .with_name("session")
+        .with_http_only(true)
+        .with_secure(true)
+        .with_same_site(SameSite::Strict)
+        .with_expiry(Expiry::OnInactivity(CookieDuration::minutes(
+            sessions.idle_minutes as i64,
+        )))
+}
+
+/// Session records in memory. Unlike tower-sessions' `MemoryStore`, which
+/// keeps every record until the process exits, expired ones are dropped by
+/// `purge_expired`.
+#[derive(Debug, Clone, Default)]
+struct LoginSessions(Arc<Mutex<HashMap<Id, Record>>>);
+
+impl LoginSessions {
+    /// Runs until the process exits
+    async fn purge_expired(self) {
+        let mut interval = tokio::time::interval(SESSION_PURGE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let now = OffsetDateTime::now_utc();
+
+            self.0.lock().unwrap().retain(|_, record| record.expiry_date > now);
+        }
+    }
+
+    /// Drops every session logged in as `username` except `keep`
+    fn end_user_sessions(&self, username: &str, keep: Option<Id>) {
+        self.0.lock().unwrap().retain(|id, record| {
+            let user = record
+                .data
+                .get(SESSION_USER)
+                .and_then(|user| SessionUser::deserialize(user).ok());
+
+            Some(*id) == keep || user.is_none_or(|user| user.username != username)
+        });
+    }
+}
+
+/// Whether the `_id` of a user document is `user_id` as sent by clients
+fn same_id(id: &Bson, user_id: &str) -> bool {
+    match id {
+        Bson::ObjectId(id) => id.to_hex() == user_id,
+        Bson::String(id) => id == user_id,
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl SessionStore for LoginSessions {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let mut records = self.0.lock().unwrap();
+
+        while records.contains_key(&record.id) {
+            record.id = Id::default();
+        }
+
+        records.insert(record.id, record.clone());
+
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.0.lock().unwrap().insert(record.id, record.clone());
+
+        Ok(())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let now = OffsetDateTime::now_utc();
+
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|record| record.expiry_date > now)
+            .cloned())
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        self.0.lock().unwrap().remove(id);
+
+        Ok(())
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|character| match character {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#x27;".to_string(),
+            _ => character.to_string(),
+        })
+        .collect()
 }
 
 async fn list_users_page(
@@ -250,46 +443,83 @@ async fn list_users_page(
     (StatusCode::OK, [("content-type", "text/html")], html).into_response()
 }
 
-fn hash_password(password: &str) -> String {
-    let password_bytes = password.as_bytes();
-    let slice = if password_bytes.len() >= 8 {
-        &password_bytes[..8]
-    } else {
-        b"password"
+/// Checks `password` against the stored hash. A legacy DES hash, or one
+/// made with other Argon2 costs than configured, is replaced by a new
+/// Argon2id hash once the password has been confirmed.
+async fn authenticate(db: &UsersDatabase, username: &str, password: &str) -> Result<Verified, String> {
+    let stored = db
+        .find_credentials(username)
+        .await
+        .map_err(|e| format!("Login error: {}", e))?;
+    let Some(stored) = stored else {
+        // Hash anyway so unknown usernames take as long as wrong passwords
+        let _ = hash_off_thread(password.to_string()).await;
+        return Ok(Verified::Invalid);
     };
-    let mut block = GenericArray::clone_from_slice(slice);
-
-    // CWE 327
-    //SINK
-    Des::new(GenericArray::from_slice(b"8bytekey")).encrypt_block(&mut block);
+    let verified = {
+        let (password, stored_hash) = (password.to_string(), stored.password_hash.clone());
 
-    hex::encode(block)
-}
-
-fn hash_password_v2(password: &str) -> String {
-    let password_bytes = password.as_bytes();
-    let slice = if password_bytes.len() >= 8 {
-        &password_bytes[..8]
-    } else {
-        b"password"
+        tokio::task::spawn_blocking(move || passwords::verify(&password, &stored_hash))
+            .await
+            .map_err(|_| "Login error")??
     };
 
-    let mut out = GenericArray::default();
+    if verified == Verified::ValidNeedsRehash {
+        let rehashed = match hash_off_thread(password.to_string()).await {
+            Ok(password_hash) => db
+                .replace_password_hash(&stored.id, &stored.password_hash, &password_hash)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+
+        // The login itself succeeded; the old hash is simply kept until next time
+        if let Err(e) = rehashed {
+            eprintln!("Failed to rehash password of {}: {}", username, secrets::redact(&e));
+        }
+    }
+
+    Ok(verified)
+}
 
-    // CWE 327
-    //SINK
-    Des::new(GenericArray::from_slice(b"8bytekey")).encrypt_block_b2b(&GenericArray::clone_from_slice(slice), &mut out);
+/// Replaces the password of `username` with a random one and returns it.
+/// The way back in for legacy accounts whose hash cannot be checked.
+pub async fn reset_password(username: &str) -> Result<String, String> {
+    let db = UsersDatabase::new()
+        .await
+        .map_err(|e| secrets::redact(&format!("数据库连接失败: {}", e)))?;
+    let stored = db
+        .find_credentials(username)
+        .await
+        .map_err(|e| secrets::redact(&format!("用户查询失败: {}", e)))?
+        .ok_or("用户不存在")?;
+    let password = passwords::temporary();
+    let password_hash = hash_off_thread(password.clone()).await?;
+    let replaced = db
+        .replace_password_hash(&stored.id, &stored.password_hash, &password_hash)
+        .await
+        .map_err(|e| secrets::redact(&format!("密码更新失败: {}", e)))?;
+
+    match replaced {
+        true => Ok(password),
+        _ => Err("密码刚被修改，请重试".into()),
+    }
+}
 
-    hex::encode(out)
+/// Argon2 takes tens of milliseconds on purpose; keep it off the async workers
+async fn hash_off_thread(password: String) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || passwords::hash(&password))
+        .await
+        .map_err(|_| "密码哈希失败")?
 }
 
-pub async fn start_users_api_server(port: u16) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn start_users_api_server(address: IpAddr, port: u16) -> Result<(), Box<dyn std::error::Error>> {
     let app = create_router().await?;
 
-    let addr = format!("0.0.0.0:{}", port);
+    let addr = SocketAddr::new(address, port);
     println!("Users API server starting on {}", addr);
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
     axum::serve(listener, app).await?;
 
     Ok(())
@@ -0,0 +1,298 @@
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::douyin_models::Aweme;
+use crate::file_finalizer::{self, ConflictPolicy};
+
+/// Excel only detects UTF-8 in a CSV when it starts with a byte order mark
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Xlsx,
+}
+
+impl ExportFormat {
+    /// Guesses the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+
+        match extension.as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" | "ndjson" => Some(ExportFormat::Jsonl),
+            "xlsx" => Some(ExportFormat::Xlsx),
+            _ => None,
+        }
+    }
+}
+
+/// One exported video. The field names are also the CSV/XLSX headers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportRow {
+    pub id: String,
+    pub desc: String,
+    pub create_time: String,
+    pub duration_secs: f64,
+    pub digg_count: u64,
+    pub comment_count: u64,
+    pub share_count: u64,
+    pub play_count: u64,
+    pub collect_count: u64,
+    pub hashtags: String,
+    pub author_uid: String,
+    pub author_sec_uid: String,
+    pub author_nickname: String,
+    pub music_id: String,
+    pub music_title: String,
+    pub music_author: String,
+    pub music_url: String,
+    pub video_url: String,
+    pub cover_url: String,
+    pub share_url: String,
+}
+
+const COLUMNS: [&str; 20] = [
+    "id",
+    "desc",
+    "create_time",
+    "duration_secs",
+    "digg_count",
+    "comment_count",
+    "share_count",
+    "play_count",
+    "collect_count",
+    "hashtags",
+    "author_uid",
+    "author_sec_uid",
+    "author_nickname",
+    "music_id",
+    "music_title",
+    "music_author",
+    "music_url",
+    "video_url",
+    "cover_url",
+    "share_url",
+];
+
+enum Cell<'a> {
+    Text(&'a str),
+    Number(f64),
+}
+
+impl ExportRow {
+    pub fn from_aweme(aweme: &Aweme) -> Self {
+        let music = aweme.music.clone().unwrap_or_default();
+
+        ExportRow {
+            id: aweme.aweme_id.clone(),
+            desc: aweme.desc.clone(),
+            create_time: chrono::DateTime::from_timestamp(aweme.create_time, 0)
+                .map(|time| time.with_timezone(&chrono::Local).to_rfc3339())
+                .unwrap_or_default(),
+            duration_secs: aweme.video.duration as f64 / 1000.0,
+            digg_count: aweme.statistics.digg_count,
+            comment_count: aweme.statistics.comment_count,
+            share_count: aweme.statistics.share_count,
+            play_count: aweme.statistics.play_count,
+            collect_count: aweme.statistics.collect_count,
+            hashtags: aweme.hashtags().join(" "),
+            author_uid: aweme.author.uid.clone(),
+            author_sec_uid: aweme.author.sec_uid.clone(),
+            author_nickname: aweme.author.nickname.clone(),
+            music_id: music.id,
+            music_title: music.title,
+            music_author: music.author,
+            music_url: music
+                .play_url
+                .map(|url| url.first().to_string())
+                .unwrap_or_default(),
+            video_url: aweme.video.play_url(),
+            cover_url: aweme.video.cover.first().to_string(),
+            share_url: format!("https://www.douyin.com/video/{}", aweme.aweme_id),
+        }
+    }
+
+    /// Values in `COLUMNS` order
+    fn cells(&self) -> [Cell<'_>; 20] {
+        [
+            Cell::Text(&self.id),
+            Cell::Text(&self.desc),
+            Cell::Text(&self.create_time),
+            Cell::Number(self.duration_secs),
+            Cell::Number(self.digg_count as f64),
+            Cell::Number(self.comment_count as f64),
+            Cell::Number(self.share_count as f64),
+            Cell::Number(self.play_count as f64),
+            Cell::Number(self.collect_count as f64),
+            Cell::Text(&self.hashtags),
+            Cell::Text(&self.author_uid),
+            Cell::Text(&self.author_sec_uid),
+            Cell::Text(&self.author_nickname),
+            Cell::Text(&self.music_id),
+            Cell::Text(&self.music_title),
+            Cell::Text(&self.music_author),
+            Cell::Text(&self.music_url),
+            Cell::Text(&self.video_url),
+            Cell::Text(&self.cover_url),
+            Cell::Text(&self.share_url),
+        ]
+    }
+}
+
+/// Writes `awemes` to `path` and returns the number of rows. The file only
+/// appears once it is complete.
+pub fn export(awemes: &[Aweme], format: ExportFormat, path: &Path) -> Result<usize, String> {
+    let rows = awemes
+        .iter()
+        .map(ExportRow::from_aweme)
+        .collect::<Vec<ExportRow>>();
+    let temp = file_finalizer::temp_path_for(path);
+    let written = match format {
+        ExportFormat::Csv => write_csv(&rows, &temp),
+        ExportFormat::Jsonl => write_jsonl(&rows, &temp),
+        ExportFormat::Xlsx => write_xlsx(&rows, &temp),
+    };
+
+    if let Err(e) = written {
+        file_finalizer::discard(&temp);
+        return Err(e);
+    }
+
+    file_finalizer::finalize(&temp, path, ConflictPolicy::Overwrite)
+        .inspect_err(|_| file_finalizer::discard(&temp))?;
+
+    Ok(rows.len())
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, String> {
+    Ok(BufWriter::new(
+        File::create(path).map_err(|_| "文件创建失败")?,
+    ))
+}
+
+fn write_csv(rows: &[ExportRow], path: &Path) -> Result<(), String> {
+    let mut file = create(path)?;
+
+    file.write_all(UTF8_BOM).map_err(|_| "文件写入失败")?;
+
+    let mut writer = csv::Writer::from_writer(file);
+
+    writer.write_record(COLUMNS).map_err(|_| "文件写入失败")?;
+
+    for row in rows {
+        let record = row.cells().map(|cell| match cell {
+            Cell::Text(text) => csv_text(text),
+            Cell::Number(number) => number.to_string(),
+        });
+
+        writer.write_record(record).map_err(|_| "文件写入失败")?;
+    }
+
+    writer.flush().map_err(|_| "文件写入失败")?;
+
+    Ok(())
+}
+
+/// Text written by other users must not run as a formula when the CSV is
+/// opened in a spreadsheet, so cells that would start one get a leading `'`
+fn csv_text(text: &str) -> String {
+    match text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", text),
+        _ => text.to_string(),
+    }
+}
+
+fn write_jsonl(rows: &[ExportRow], path: &Path) -> Result<(), String> {
+    let mut file = create(path)?;
+
+    for row in rows {
+        serde_json::to_writer(&mut file, row).map_err(|_| "文件写入失败")?;
+        file.write_all(b"\n").map_err(|_| "文件写入失败")?;
+    }
+
+    file.flush().map_err(|_| "文件写入失败")?;
+
+    Ok(())
+}
+
+fn write_xlsx(rows: &[ExportRow], path: &Path) -> Result<(), String> {
+    let mut workbook = Workbook::new();
+    let header = Format::new().set_bold();
+    let sheet = workbook.add_worksheet();
+    let xlsx_error = |_: XlsxError| "Excel 写入失败";
+
+    sheet.set_name("videos").map_err(xlsx_error)?;
+
+    for (col, name) in COLUMNS.iter().enumerate() {
+        sheet
+            .write_string_with_format(0, col as u16, *name, &header)
+            .map_err(xlsx_error)?;
+    }
+
+    for (index, row) in rows.iter().enumerate() {
+        let row_num = index as u32 + 1;
+
+        for (col, cell) in row.cells().into_iter().enumerate() {
+            match cell {
+                Cell::Text(value) => sheet.write_string(row_num, col as u16, value),
+                Cell::Number(value) => sheet.write_number(row_num, col as u16, value),
+            }
+            .map_err(xlsx_error)?;
+        }
+    }
+
+    sheet.set_column_width(1, 60).map_err(xlsx_error)?;
+    sheet.set_freeze_panes(1, 0).map_err(xlsx_error)?;
+    sheet
+        .autofilter(0, 0, rows.len() as u32, COLUMNS.len() as u16 - 1)
+        .map_err(xlsx_error)?;
+    workbook.save(path).map_err(xlsx_error)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_text_defuses_formulas() {
+        for text in ["=1+1", "+1", "-1", "@SUM(A1)", "\t=1", "\r=1"] {
+            assert_eq!(csv_text(text), format!("'{}", text));
+        }
+
+        assert_eq!(csv_text("a=1"), "a=1");
+        assert_eq!(csv_text(""), "");
+    }
+
+    #[test]
+    fn csv_export_escapes_user_text() {
+        let aweme = crate::douyin_models::parse::<Aweme>(&serde_json::json!({
+            "aweme_id": "7",
+            "desc": "=HYPERLINK(\"http://example.com\")",
+            "author": { "nickname": "@evil" },
+            "music": { "title": "-2+3" },
+            "video": { "play_addr": { "url_list": ["https://example.com/v"] }, "duration": 1500 },
+        }))
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("video_export_{}.csv", std::process::id()));
+
+        export(&[aweme], ExportFormat::Csv, &path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut reader = csv::Reader::from_reader(content.trim_start_matches('\u{feff}').as_bytes());
+        let record = reader.records().next().unwrap().unwrap();
+
+        assert_eq!(&record[1], "'=HYPERLINK(\"http://example.com\")");
+        assert_eq!(&record[3], "1.5");
+        assert_eq!(&record[12], "'@evil");
+        assert_eq!(&record[14], "'-2+3");
+    }
+}